@@ -1,9 +1,14 @@
-use rodio::{OutputStream, Sink, Source};
-
+use crate::consts::CPU_CLOCK_SPEED;
+use crate::savestate::{Savable, SaveStateError, StateReader, StateWriter};
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
 
-const SAMPLE_RATE: u32 = 65536;
+pub const SAMPLE_RATE: u32 = 65536;
+
+const CYCLES_PER_SAMPLE: u64 = CPU_CLOCK_SPEED / SAMPLE_RATE as u64;
+
+// One second of samples is kept by default when nobody drains them
+const DEFAULT_SAMPLES_LIMIT: usize = SAMPLE_RATE as usize;
 
 const SAMPLE_AVERAGING: usize = 5; //20;
 
@@ -39,7 +44,7 @@ const SQUARE_WAVE_PATTERNS: [[u8; 32]; 4] = [
     SQUARE_WAVE_PATTERN_DUTY_3,
 ];
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct Wave {
     period_value: u16,
     num_sample: usize,
@@ -57,6 +62,7 @@ pub struct Wave {
 }
 
 impl Wave {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         period_value: u16,
         wave_pattern: [u8; 32],
@@ -105,7 +111,7 @@ impl Iterator for Wave {
         }
 
         if self.period_sweep_slope != 0 && self.period_sweep_pace != 0 {
-            let sweep_i = ((self.num_sample as f32 * (32768 as f32 / SAMPLE_RATE as f32)) as u32
+            let sweep_i = ((self.num_sample as f32 * (32768_f32 / SAMPLE_RATE as f32)) as u32
                 / 256)
                 / self.period_sweep_pace as u32;
 
@@ -138,13 +144,7 @@ impl Iterator for Wave {
 
         let envelope = self.env_initial_volume + (self.env_direction * envelope_time);
 
-        let envelope_boundaries = if envelope > 16. {
-            16.
-        } else if envelope < 0. {
-            0.
-        } else {
-            envelope
-        };
+        let envelope_boundaries = envelope.clamp(0., 16.);
 
         let mut avg = 0.;
 
@@ -165,7 +165,39 @@ impl Iterator for Wave {
     }
 }
 
-#[derive(Clone, Debug)]
+impl Savable for Wave {
+    fn save(&self, w: &mut StateWriter) {
+        w.u16(self.period_value);
+        w.u64(self.num_sample as u64);
+        w.bytes(&self.wave_pattern);
+        w.u8(self.length_timer);
+        w.bool(self.length_timer_enabled);
+        w.f32(self.env_initial_volume);
+        w.f32(self.env_direction);
+        w.u8(self.env_sweep_pace);
+        w.u8(self.period_sweep_pace);
+        w.u8(self.period_sweep_direction);
+        w.u8(self.period_sweep_slope);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.period_value = r.u16()?;
+        self.num_sample = r.u64()? as usize;
+        r.bytes(&mut self.wave_pattern)?;
+        self.length_timer = r.u8()?;
+        self.length_timer_enabled = r.bool()?;
+        self.env_initial_volume = r.f32()?;
+        self.env_direction = r.f32()?;
+        self.env_sweep_pace = r.u8()?;
+        self.period_sweep_pace = r.u8()?;
+        self.period_sweep_direction = r.u8()?;
+        self.period_sweep_slope = r.u8()?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct NoiseWave {
     num_sample: usize,
     length_timer: u8,
@@ -181,6 +213,7 @@ pub struct NoiseWave {
 }
 
 impl NoiseWave {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         env_initial_volume: u8,
         env_direction: u8,
@@ -232,13 +265,7 @@ impl Iterator for NoiseWave {
 
         let envelope = self.env_initial_volume + (self.env_direction * envelope_time);
 
-        let envelope_boundaries = if envelope > 16. {
-            16.
-        } else if envelope < 0. {
-            0.
-        } else {
-            envelope
-        };
+        let envelope_boundaries = envelope.clamp(0., 16.);
 
         let mut avg = 0.;
 
@@ -247,7 +274,7 @@ impl Iterator for NoiseWave {
                 let ns = ((262144. / ((clock_divider) * (2 << self.clock_shift) as f32)) / 32768.)
                     *  (self.num_sample + n - (SAMPLE_AVERAGING / 2)) as f32;
 
-                let i = (ns as f32 * (32768 as f32 / SAMPLE_RATE as f32)) as usize;
+                let i = (ns * (32768_f32 / SAMPLE_RATE as f32)) as usize;
 
                 let up = if self.lsfr_width == 1 {
                     NOISE_WAVE[i % 63]
@@ -263,8 +290,38 @@ impl Iterator for NoiseWave {
     }
 }
 
+impl Savable for NoiseWave {
+    fn save(&self, w: &mut StateWriter) {
+        w.u64(self.num_sample as u64);
+        w.u8(self.length_timer);
+        w.bool(self.length_timer_enabled);
+        w.f32(self.env_initial_volume);
+        w.f32(self.env_direction);
+        w.u8(self.env_sweep_pace);
+        w.u8(self.clock_shift);
+        w.u8(self.lsfr_width);
+        w.u8(self.clock_divider);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.num_sample = r.u64()? as usize;
+        self.length_timer = r.u8()?;
+        self.length_timer_enabled = r.bool()?;
+        self.env_initial_volume = r.f32()?;
+        self.env_direction = r.f32()?;
+        self.env_sweep_pace = r.u8()?;
+        self.clock_shift = r.u8()?;
+        self.lsfr_width = r.u8()?;
+        self.clock_divider = r.u8()?;
+
+        Ok(())
+    }
+}
+
+/// Mixes the four channels into a mono stream of samples at `SAMPLE_RATE`.
+/// Clones share the same channel state.
 #[derive(Clone, Debug)]
-struct MutableWave {
+pub struct MutableWave {
     wave_ch1: Arc<Mutex<Option<Wave>>>,
     wave_ch2: Arc<Mutex<Option<Wave>>>,
     wave_ch3: Arc<Mutex<Option<Wave>>>,
@@ -337,24 +394,6 @@ impl Iterator for MutableWave {
     }
 }
 
-impl Source for MutableWave {
-    fn current_frame_len(&self) -> Option<usize> {
-        None
-    }
-
-    fn channels(&self) -> u16 {
-        1
-    }
-
-    fn sample_rate(&self) -> u32 {
-        SAMPLE_RATE
-    }
-
-    fn total_duration(&self) -> Option<Duration> {
-        None
-    }
-}
-
 pub struct AudioSquareChannel {
     wave: Arc<Mutex<Option<Wave>>>,
 
@@ -411,6 +450,40 @@ impl AudioSquareChannel {
     }
 }
 
+impl Savable for AudioSquareChannel {
+    fn save(&self, w: &mut StateWriter) {
+        self.wave.lock().unwrap().save(w);
+        w.u8(self.length_timer);
+        w.bool(self.length_timer_enabled);
+        w.bool(self.on);
+        w.u16(self.period_value);
+        w.u8(self.duty);
+        w.u8(self.initial_volume);
+        w.u8(self.env_direction);
+        w.u8(self.sweep);
+        w.u8(self.period_sweep_pace);
+        w.u8(self.period_sweep_direction);
+        w.u8(self.period_sweep_slope);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.wave.lock().unwrap().load(r)?;
+        self.length_timer = r.u8()?;
+        self.length_timer_enabled = r.bool()?;
+        self.on = r.bool()?;
+        self.period_value = r.u16()?;
+        self.duty = r.u8()? & 0b11;
+        self.initial_volume = r.u8()?;
+        self.env_direction = r.u8()?;
+        self.sweep = r.u8()?;
+        self.period_sweep_pace = r.u8()?;
+        self.period_sweep_direction = r.u8()?;
+        self.period_sweep_slope = r.u8()?;
+
+        Ok(())
+    }
+}
+
 pub struct AudioCustomChannel {
     wave: Arc<Mutex<Option<Wave>>>,
 
@@ -457,6 +530,30 @@ impl AudioCustomChannel {
     }
 }
 
+impl Savable for AudioCustomChannel {
+    fn save(&self, w: &mut StateWriter) {
+        self.wave.lock().unwrap().save(w);
+        w.u8(self.length_timer);
+        w.bool(self.length_timer_enabled);
+        w.bytes(&self.wave_pattern);
+        w.bool(self.on);
+        w.u16(self.period_value);
+        w.u8(self.initial_volume);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.wave.lock().unwrap().load(r)?;
+        self.length_timer = r.u8()?;
+        self.length_timer_enabled = r.bool()?;
+        r.bytes(&mut self.wave_pattern)?;
+        self.on = r.bool()?;
+        self.period_value = r.u16()?;
+        self.initial_volume = r.u8()?;
+
+        Ok(())
+    }
+}
+
 pub struct AudioNoiseChannel {
     wave: Arc<Mutex<Option<NoiseWave>>>,
 
@@ -507,9 +604,45 @@ impl AudioNoiseChannel {
     }
 }
 
+impl Savable for AudioNoiseChannel {
+    fn save(&self, w: &mut StateWriter) {
+        self.wave.lock().unwrap().save(w);
+        w.u8(self.length_timer);
+        w.bool(self.length_timer_enabled);
+        w.bool(self.on);
+        w.u8(self.initial_volume);
+        w.u8(self.env_direction);
+        w.u8(self.sweep);
+        w.u8(self.clock_shift);
+        w.u8(self.lsfr_width);
+        w.u8(self.clock_divider);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.wave.lock().unwrap().load(r)?;
+        self.length_timer = r.u8()?;
+        self.length_timer_enabled = r.bool()?;
+        self.on = r.bool()?;
+        self.initial_volume = r.u8()?;
+        self.env_direction = r.u8()?;
+        self.sweep = r.u8()?;
+        self.clock_shift = r.u8()?;
+        self.lsfr_width = r.u8()?;
+        self.clock_divider = r.u8()?;
+
+        Ok(())
+    }
+}
+
 pub struct Audio {
-    _stream: OutputStream,
-    _sink: Sink,
+    wave: MutableWave,
+
+    // Samples generated while the emulator runs, until a frontend drains them.
+    // `None` when a frontend pulls from the mixer on its own clock instead.
+    samples: Option<VecDeque<f32>>,
+    // `None` keeps every sample until they are drained
+    samples_limit: Option<usize>,
+    sample_cycles: u64,
 
     pub ch1: AudioSquareChannel,
     pub ch2: AudioSquareChannel,
@@ -519,25 +652,23 @@ pub struct Audio {
 
 impl Audio {
     pub fn new() -> Self {
-        let (stream, stream_handle) = OutputStream::try_default().unwrap();
-
-        let sink = Sink::try_new(&stream_handle).unwrap();
-
         let wave_ch1 = Arc::new(Mutex::new(None));
         let wave_ch2 = Arc::new(Mutex::new(None));
         let wave_ch3 = Arc::new(Mutex::new(None));
         let wave_ch4 = Arc::new(Mutex::new(None));
 
-        sink.append(MutableWave::new(
+        let wave = MutableWave::new(
             wave_ch1.clone(),
             wave_ch2.clone(),
             wave_ch3.clone(),
             wave_ch4.clone(),
-        ));
+        );
 
         Self {
-            _stream: stream,
-            _sink: sink,
+            wave,
+            samples: Some(VecDeque::new()),
+            samples_limit: Some(DEFAULT_SAMPLES_LIMIT),
+            sample_cycles: 0,
 
             ch1: AudioSquareChannel::new(wave_ch1),
             ch2: AudioSquareChannel::new(wave_ch2),
@@ -545,4 +676,67 @@ impl Audio {
             ch4: AudioNoiseChannel::new(wave_ch4),
         }
     }
+
+    /// Hands the mixer over to a frontend that pulls samples in real time.
+    /// The emulator stops buffering samples from then on.
+    pub fn source(&mut self) -> MutableWave {
+        self.samples = None;
+        self.wave.clone()
+    }
+
+    /// Caps the number of buffered samples (one second by default), the oldest
+    /// ones being dropped when nobody drains them in time.
+    pub fn set_samples_limit(&mut self, limit: Option<usize>) {
+        self.samples_limit = limit;
+    }
+
+    pub fn tick(&mut self, c: u64) {
+        if let Some(samples) = self.samples.as_mut() {
+            self.sample_cycles += c;
+            while self.sample_cycles >= CYCLES_PER_SAMPLE {
+                self.sample_cycles -= CYCLES_PER_SAMPLE;
+                samples.push_back(self.wave.next().unwrap_or(0.));
+
+                if let Some(limit) = self.samples_limit {
+                    while samples.len() > limit {
+                        samples.pop_front();
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        match self.samples.as_mut() {
+            Some(samples) => samples.drain(..).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Savable for Audio {
+    fn save(&self, w: &mut StateWriter) {
+        w.u64(self.sample_cycles);
+        self.ch1.save(w);
+        self.ch2.save(w);
+        self.ch3.save(w);
+        self.ch4.save(w);
+    }
+
+    // The buffered samples are left alone, they were already produced
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.sample_cycles = r.u64()?;
+        self.ch1.load(r)?;
+        self.ch2.load(r)?;
+        self.ch3.load(r)?;
+        self.ch4.load(r)?;
+
+        Ok(())
+    }
 }
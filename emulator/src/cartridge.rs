@@ -0,0 +1,453 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+
+const HEADER_END: usize = 0x150;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MbcKind {
+    None,
+    Mbc1,
+    Mbc2,
+    Mmm01,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    PocketCamera,
+    Tama5,
+    HuC3,
+    HuC1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mbc: MbcKind,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+}
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> Option<Self> {
+        // (MBC, RAM, BATTERY, TIMER, RUMBLE)
+        let (mbc, ram, battery, timer, rumble) = match code {
+            0x00 => (MbcKind::None, false, false, false, false),
+            0x01 => (MbcKind::Mbc1, false, false, false, false),
+            0x02 => (MbcKind::Mbc1, true, false, false, false),
+            0x03 => (MbcKind::Mbc1, true, true, false, false),
+            0x05 => (MbcKind::Mbc2, false, false, false, false),
+            0x06 => (MbcKind::Mbc2, false, true, false, false),
+            0x08 => (MbcKind::None, true, false, false, false),
+            0x09 => (MbcKind::None, true, true, false, false),
+            0x0b => (MbcKind::Mmm01, false, false, false, false),
+            0x0c => (MbcKind::Mmm01, true, false, false, false),
+            0x0d => (MbcKind::Mmm01, true, true, false, false),
+            0x0f => (MbcKind::Mbc3, false, true, true, false),
+            0x10 => (MbcKind::Mbc3, true, true, true, false),
+            0x11 => (MbcKind::Mbc3, false, false, false, false),
+            0x12 => (MbcKind::Mbc3, true, false, false, false),
+            0x13 => (MbcKind::Mbc3, true, true, false, false),
+            0x19 => (MbcKind::Mbc5, false, false, false, false),
+            0x1a => (MbcKind::Mbc5, true, false, false, false),
+            0x1b => (MbcKind::Mbc5, true, true, false, false),
+            0x1c => (MbcKind::Mbc5, false, false, false, true),
+            0x1d => (MbcKind::Mbc5, true, false, false, true),
+            0x1e => (MbcKind::Mbc5, true, true, false, true),
+            0x20 => (MbcKind::Mbc6, false, false, false, false),
+            0x22 => (MbcKind::Mbc7, true, true, false, true),
+            0xfc => (MbcKind::PocketCamera, false, false, false, false),
+            0xfd => (MbcKind::Tama5, false, false, false, false),
+            0xfe => (MbcKind::HuC3, false, false, false, false),
+            0xff => (MbcKind::HuC1, true, true, false, false),
+            _ => return None,
+        };
+
+        Some(Self {
+            code,
+            mbc,
+            ram,
+            battery,
+            timer,
+            rumble,
+        })
+    }
+}
+
+impl fmt::Display for CartridgeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mbc {
+            MbcKind::None => write!(f, "ROM")?,
+            MbcKind::PocketCamera => write!(f, "POCKET CAMERA")?,
+            MbcKind::Tama5 => write!(f, "TAMA5")?,
+            MbcKind::HuC3 => write!(f, "HuC3")?,
+            MbcKind::HuC1 => write!(f, "HuC1")?,
+            mbc => write!(f, "{}", format!("{:?}", mbc).to_uppercase())?,
+        }
+        if self.timer {
+            write!(f, "+TIMER")?;
+        }
+        if self.ram {
+            write!(f, "+RAM")?;
+        }
+        if self.battery {
+            write!(f, "+BATTERY")?;
+        }
+        if self.rumble {
+            write!(f, "+RUMBLE")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Licensee {
+    // 0x014b, used by older cartridges
+    Old(u8),
+    // 0x0144-0x0145, used when 0x014b is 0x33
+    New(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub title: String,
+    // 4 characters manufacturer code of the later CGB cartridges
+    pub manufacturer: Option<String>,
+    pub cgb_flag: u8,
+    pub sgb_flag: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub licensee: Licensee,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(std::io::Error),
+    // The file is too short to even contain a header
+    NoHeader(usize),
+    UnknownCartridgeType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    Truncated { expected: usize, actual: usize },
+    Oversized { expected: usize, actual: usize },
+    // The header is valid but the emulator can't run it
+    Unsupported(String),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(e) => write!(f, "{}", e),
+            CartridgeError::NoHeader(size) => write!(
+                f,
+                "the file is {} bytes long, too short to contain a cartridge header (0x{:x} bytes)",
+                size, HEADER_END
+            ),
+            CartridgeError::UnknownCartridgeType(code) => {
+                write!(f, "unknown cartridge type 0x{:02x}", code)
+            }
+            CartridgeError::UnknownRomSize(code) => write!(f, "unknown ROM size 0x{:02x}", code),
+            CartridgeError::UnknownRamSize(code) => write!(f, "unknown RAM size 0x{:02x}", code),
+            CartridgeError::Truncated { expected, actual } => write!(
+                f,
+                "the ROM is truncated: the header declares {} bytes but the file is {} bytes long",
+                expected, actual
+            ),
+            CartridgeError::Oversized { expected, actual } => write!(
+                f,
+                "the ROM is oversized: the header declares {} bytes but the file is {} bytes long",
+                expected, actual
+            ),
+            CartridgeError::Unsupported(reason) => write!(f, "unsupported cartridge: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl From<std::io::Error> for CartridgeError {
+    fn from(e: std::io::Error) -> Self {
+        CartridgeError::Io(e)
+    }
+}
+
+fn ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&b| b != 0)
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '?'
+            }
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+impl Header {
+    pub fn parse(rom: &[u8]) -> Result<Self, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::NoHeader(rom.len()));
+        }
+
+        let cgb_flag = rom[0x143];
+        let is_cgb = cgb_flag & 0x80 != 0;
+
+        let manufacturer_code = &rom[0x13f..0x143];
+        let manufacturer = if is_cgb
+            && manufacturer_code
+                .iter()
+                .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+        {
+            Some(ascii(manufacturer_code))
+        } else {
+            None
+        };
+
+        let title = ascii(match (is_cgb, &manufacturer) {
+            (_, Some(_)) => &rom[0x134..0x13f],
+            (true, None) => &rom[0x134..0x143],
+            (false, None) => &rom[0x134..0x144],
+        });
+
+        let cartridge_type = CartridgeType::from_code(rom[0x147])
+            .ok_or(CartridgeError::UnknownCartridgeType(rom[0x147]))?;
+
+        let rom_size = match rom[0x148] {
+            n @ 0x00..=0x08 => (ROM_BANK_SIZE * 2) << n,
+            n => return Err(CartridgeError::UnknownRomSize(n)),
+        };
+
+        let ram_size = match rom[0x149] {
+            0x00 => 0,
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            n => return Err(CartridgeError::UnknownRamSize(n)),
+        };
+
+        let licensee = if rom[0x14b] == 0x33 {
+            Licensee::New(ascii(&rom[0x144..0x146]))
+        } else {
+            Licensee::Old(rom[0x14b])
+        };
+
+        Ok(Self {
+            title,
+            manufacturer,
+            cgb_flag,
+            sgb_flag: rom[0x146] == 0x03,
+            cartridge_type,
+            rom_size,
+            ram_size,
+            licensee,
+            version: rom[0x14c],
+            header_checksum: rom[0x14d],
+            global_checksum: ((rom[0x14e] as u16) << 8) | rom[0x14f] as u16,
+        })
+    }
+
+    pub fn cgb(&self) -> bool {
+        self.cgb_flag == 0x80 || self.cgb_flag == 0xc0
+    }
+
+    pub fn compute_header_checksum(rom: &[u8]) -> u8 {
+        rom[0x134..0x14d]
+            .iter()
+            .fold(0u8, |acc, b| acc.wrapping_sub(*b).wrapping_sub(1))
+    }
+
+    pub fn compute_global_checksum(rom: &[u8]) -> u16 {
+        rom.iter()
+            .enumerate()
+            .filter(|(i, _)| *i != 0x14e && *i != 0x14f)
+            .fold(0u16, |acc, (_, b)| acc.wrapping_add(*b as u16))
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "\"{}\" v{} ({}, ROM: {} KiB, RAM: {} KiB{}{})",
+            self.title,
+            self.version,
+            self.cartridge_type,
+            self.rom_size / 1024,
+            self.ram_size / 1024,
+            if self.cgb() { ", CGB" } else { "" },
+            if self.sgb_flag { ", SGB" } else { "" },
+        )
+    }
+}
+
+pub struct Cartridge {
+    pub header: Header,
+    pub rom: Vec<u8>,
+}
+
+impl Cartridge {
+    pub fn from_file(file: &str) -> Result<Self, CartridgeError> {
+        let mut f = File::open(file)?;
+        let mut rom = Vec::new();
+
+        f.read_to_end(&mut rom)?;
+
+        Self::from_bytes(rom)
+    }
+
+    pub fn from_bytes(mut rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        let header = Header::parse(&rom)?;
+
+        if rom.len() > header.rom_size {
+            return Err(CartridgeError::Oversized {
+                expected: header.rom_size,
+                actual: rom.len(),
+            });
+        }
+
+        if rom.len() < header.rom_size {
+            // Small homebrew ROMs (like the ones made with the assembler in
+            // this repository) often don't bother padding to the minimum
+            // 32 KiB, there is nothing missing from them.
+            if header.rom_size != ROM_BANK_SIZE * 2 {
+                return Err(CartridgeError::Truncated {
+                    expected: header.rom_size,
+                    actual: rom.len(),
+                });
+            }
+            rom.resize(header.rom_size, 0);
+        }
+
+        Ok(Self { header, rom })
+    }
+
+    pub fn header_checksum_valid(&self) -> bool {
+        Header::compute_header_checksum(&self.rom) == self.header.header_checksum
+    }
+
+    pub fn global_checksum_valid(&self) -> bool {
+        Header::compute_global_checksum(&self.rom) == self.header.global_checksum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(title: &[u8], cgb_flag: u8, cartridge_type: u8, rom_size: u8) -> Vec<u8> {
+        let mut rom = vec![0; (ROM_BANK_SIZE * 2) << rom_size];
+
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x143] = cgb_flag;
+        rom[0x147] = cartridge_type;
+        rom[0x148] = rom_size;
+        rom[0x14d] = Header::compute_header_checksum(&rom);
+
+        let global_checksum = Header::compute_global_checksum(&rom);
+        rom[0x14e..0x150].copy_from_slice(&global_checksum.to_be_bytes());
+
+        rom
+    }
+
+    #[test]
+    fn dmg_header() {
+        let header = Header::parse(&rom(b"POKEMON RED", 0x00, 0x13, 0x05)).unwrap();
+
+        assert_eq!(header.title, "POKEMON RED");
+        assert_eq!(header.manufacturer, None);
+        assert!(!header.cgb());
+        assert_eq!(header.cartridge_type.mbc, MbcKind::Mbc3);
+        assert!(header.cartridge_type.ram && header.cartridge_type.battery);
+        assert_eq!(header.rom_size, 1024 * 1024);
+    }
+
+    #[test]
+    fn cgb_header() {
+        // The last 4 characters are the manufacturer code on CGB cartridges
+        let header = Header::parse(&rom(b"ZELDA DX\0\0\0AZ7E", 0x80, 0x1b, 0x00)).unwrap();
+        assert_eq!(header.title, "ZELDA DX");
+        assert_eq!(header.manufacturer.as_deref(), Some("AZ7E"));
+        assert!(header.cgb());
+
+        // Which older ones used for the title
+        let header = Header::parse(&rom(b"TETRIS DX", 0xc0, 0x00, 0x00)).unwrap();
+        assert_eq!(header.title, "TETRIS DX");
+        assert_eq!(header.manufacturer, None);
+        assert!(header.cgb());
+    }
+
+    #[test]
+    fn checksums() {
+        let mut rom = rom(b"TEST", 0x00, 0x01, 0x01);
+
+        let cartridge = Cartridge::from_bytes(rom.clone()).unwrap();
+        assert!(cartridge.header_checksum_valid());
+        assert!(cartridge.global_checksum_valid());
+
+        rom[0x134] = b'B';
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        assert!(!cartridge.header_checksum_valid());
+        assert!(!cartridge.global_checksum_valid());
+    }
+
+    #[test]
+    fn invalid_headers() {
+        assert!(matches!(
+            Header::parse(&[0; 0x100]),
+            Err(CartridgeError::NoHeader(0x100))
+        ));
+        assert!(matches!(
+            Header::parse(&rom(b"TEST", 0x00, 0x04, 0x00)),
+            Err(CartridgeError::UnknownCartridgeType(0x04))
+        ));
+
+        let mut rom = rom(b"TEST", 0x00, 0x01, 0x00);
+        rom[0x148] = 0x09;
+        assert!(matches!(
+            Header::parse(&rom),
+            Err(CartridgeError::UnknownRomSize(0x09))
+        ));
+    }
+
+    #[test]
+    fn rom_size_mismatch() {
+        let mut rom = rom(b"TEST", 0x00, 0x01, 0x01);
+
+        rom.truncate(0xc000);
+        assert!(matches!(
+            Cartridge::from_bytes(rom.clone()),
+            Err(CartridgeError::Truncated {
+                expected: 0x10000,
+                actual: 0xc000
+            })
+        ));
+
+        rom.resize(0x14000, 0);
+        assert!(matches!(
+            Cartridge::from_bytes(rom),
+            Err(CartridgeError::Oversized { .. })
+        ));
+    }
+
+    #[test]
+    fn unpadded_32kib_rom() {
+        let mut rom = rom(b"TEST", 0x00, 0x00, 0x00);
+        rom.truncate(0x200);
+
+        assert_eq!(Cartridge::from_bytes(rom).unwrap().rom.len(), 0x8000);
+    }
+}
@@ -10,3 +10,5 @@ pub const DISPLAY_UPDATE_SLEEP_TIME_MICROS: u64 =
 pub const CPU_CLOCK_SPEED: u64 = 4_194_304;
 pub const CPU_CYCLE_LENGTH_NANOS: u64 =
     ((1_000_000_000 / CPU_CLOCK_SPEED) as f64 / SPEEDUP_FACTOR) as u64;
+
+pub const CYCLES_PER_FRAME: u64 = 456 * 154;
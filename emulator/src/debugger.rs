@@ -0,0 +1,715 @@
+use crate::disasm::{self, parse_hex};
+use crate::state::{flag, reg, GBState};
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+Commands:
+  s, step [n]              execute n instructions (1 by default)
+  n, next                  step over calls and RSTs
+  f, finish                run until the current function returns
+  c, continue              run until a breakpoint
+  b, break [bank:]addr     add a breakpoint, the bank applies to 0x4000-0x7fff
+  d, delete <n>            delete breakpoint number n
+  bl, breakpoints          list the breakpoints
+  watch <range> [value]    stop after a write, optionally only of this value
+  rwatch <range> [value]   stop after a read
+  cwatch <range> [value]   stop after a write changing the value
+  unwatch <n>              delete watchpoint number n
+  wl, watchpoints          list the watchpoints
+  r, regs                  print the registers and flags
+  l, disasm [addr] [n]     disassemble n instructions (0xa from PC by default)
+  x, mem <addr> [len]      dump memory (0x40 bytes by default)
+  detach                   leave the debugger, ignoring all the breakpoints
+  q, quit                  exit the emulator
+An empty line repeats the last command. All numbers are hexadecimal, the
+breakpoint and watchpoint numbers and counts included. A range is either an
+address or start-end (inclusive).";
+
+// CALL nn and CALL cc, nn
+const CALL_OPCODES: [u8; 5] = [0xcd, 0xc4, 0xcc, 0xd4, 0xdc];
+
+// RET, RETI and RET cc
+const RET_OPCODES: [u8; 6] = [0xc9, 0xd9, 0xc0, 0xc8, 0xd0, 0xd8];
+
+fn is_rst(opcode: u8) -> bool {
+    opcode & 0b11000111 == 0b11000111
+}
+
+/// What the emulator has to do once the debugger returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebuggerAction {
+    // Execute the next instruction
+    Resume,
+    // Stop the emulation, `quit` was typed
+    Quit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    // ROM bank the address must be mapped from, any bank if None
+    pub bank: Option<usize>,
+}
+
+impl Breakpoint {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (bank, addr) = disasm::parse_location(s)?;
+
+        Some(Self { addr, bank })
+    }
+
+    fn hit(&self, state: &GBState) -> bool {
+        state.cpu.pc == self.addr
+            && self
+                .bank
+                .is_none_or(|bank| bank == current_bank(state, self.addr))
+    }
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{:02x}:{:04x}", bank, self.addr),
+            None => write!(f, "{:04x}", self.addr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    // Writes of a value different from the current one
+    Change,
+}
+
+/// Checked by `Memory` on every read or write, see `Memory::watchpoints`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub kind: WatchKind,
+    // Inclusive address range
+    pub start: u16,
+    pub end: u16,
+    // Only trigger when this value is read or written
+    pub value: Option<u8>,
+}
+
+impl Watchpoint {
+    pub fn parse(kind: WatchKind, range: &str, value: Option<&str>) -> Option<Self> {
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_hex(start)?, parse_hex(end)?),
+            None => {
+                let addr = parse_hex(range)?;
+                (addr, addr)
+            }
+        };
+
+        if start > end {
+            return None;
+        }
+
+        Some(Self {
+            kind,
+            start,
+            end,
+            value: match value {
+                Some(value) => Some(parse_hex(value)?),
+                None => None,
+            },
+        })
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        (self.start..=self.end).contains(&addr)
+    }
+
+    /// Whether an access to a watched address triggers the watchpoint. For
+    /// reads, `old` and `new` are both the value read.
+    pub fn triggers(&self, write: bool, old: u8, new: u8) -> bool {
+        let kind = match self.kind {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::Change => write && old != new,
+        };
+
+        kind && self.value.is_none_or(|value| value == new)
+    }
+}
+
+impl std::fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let kind = match self.kind {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Change => "change",
+        };
+
+        write!(f, "{} {:04x}", kind, self.start)?;
+        if self.end != self.start {
+            write!(f, "-{:04x}", self.end)?;
+        }
+        if let Some(value) = self.value {
+            write!(f, " == {:02x}", value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An access that triggered a watchpoint
+#[derive(Debug, Clone, Copy)]
+pub struct WatchpointHit {
+    // Index in `Memory::watchpoints`
+    pub index: usize,
+    pub kind: WatchKind,
+    pub addr: u16,
+    pub old: u8,
+    pub new: u8,
+}
+
+/// Bank an address is currently mapped from, only meaningful for the ROM
+fn current_bank(state: &GBState, addr: u16) -> usize {
+    if (0x4000..0x8000).contains(&addr) {
+        state.mem.rom_bank()
+    } else {
+        0
+    }
+}
+
+// What the debugger is waiting for before showing the prompt again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunMode {
+    Stopped,
+    Steps(u64),
+    // Step over: until the instruction after the call, in the same stack frame
+    Until { pc: u16, sp: u16 },
+    // Until a RET pops the stack above this SP
+    Finish { sp: u16 },
+    Continue,
+}
+
+/// Command line debugger reading its commands from stdin, or the input given
+/// to `with_input`. It is checked before each instruction by
+/// `Emulator::step_instruction`.
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+
+    mode: RunMode,
+    last_command: String,
+    // Opcode of the previous instruction, to detect returns
+    last_opcode: u8,
+    // Where the previous instruction was, the watchpoints trigger during it
+    last_pc: u16,
+    last_bank: usize,
+
+    // Set by the `detach` command or when the input is closed
+    pub detached: bool,
+
+    // Where the commands are read from, stdin when None
+    input: Option<Box<dyn BufRead + Send>>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            mode: RunMode::Continue,
+            last_command: String::new(),
+            last_opcode: 0,
+            last_pc: 0,
+            last_bank: 0,
+            detached: false,
+            input: None,
+        }
+    }
+
+    /// Reads the commands from `input` instead of stdin, e.g. a script
+    pub fn with_input(input: Box<dyn BufRead + Send>) -> Self {
+        Self {
+            input: Some(input),
+            ..Self::new()
+        }
+    }
+
+    /// Stops before the next instruction
+    pub fn break_now(&mut self) {
+        self.mode = RunMode::Stopped;
+        self.detached = false;
+    }
+
+    fn should_stop(&mut self, state: &GBState) -> bool {
+        let stop = match self.mode {
+            RunMode::Stopped => true,
+            RunMode::Steps(n) => n == 0,
+            RunMode::Until { pc, sp } => state.cpu.pc == pc && state.cpu.sp >= sp,
+            RunMode::Finish { sp } => RET_OPCODES.contains(&self.last_opcode) && state.cpu.sp > sp,
+            RunMode::Continue => false,
+        };
+
+        if stop {
+            return true;
+        }
+
+        if let Some(i) = self.breakpoints.iter().position(|b| b.hit(state)) {
+            println!("Breakpoint {:x} at {}", i, self.breakpoints[i]);
+            return true;
+        }
+
+        false
+    }
+
+    /// Called before each instruction, shows the prompt when the execution
+    /// has to stop there.
+    pub fn before_instruction(&mut self, state: &mut GBState) -> DebuggerAction {
+        let hits = state.mem.take_watchpoint_hits();
+
+        if self.detached {
+            return DebuggerAction::Resume;
+        }
+
+        for hit in &hits {
+            self.print_watchpoint_hit(state, hit);
+        }
+
+        if self.should_stop(state) || !hits.is_empty() {
+            self.mode = RunMode::Stopped;
+            self.print_location(state);
+            if self.prompt(state) == DebuggerAction::Quit {
+                return DebuggerAction::Quit;
+            }
+        }
+
+        if let RunMode::Steps(n) = self.mode {
+            self.mode = RunMode::Steps(n - 1);
+        }
+        self.last_opcode = state.mem.peek(state.cpu.pc);
+        self.last_pc = state.cpu.pc;
+        self.last_bank = current_bank(state, state.cpu.pc);
+
+        DebuggerAction::Resume
+    }
+
+    fn print_watchpoint_hit(&self, state: &GBState, hit: &WatchpointHit) {
+        let watchpoint = state.mem.watchpoints.get(hit.index);
+        let access = match hit.kind {
+            WatchKind::Read => format!("read {:02x}", hit.new),
+            WatchKind::Write | WatchKind::Change => {
+                format!("write {:02x} -> {:02x}", hit.old, hit.new)
+            }
+        };
+
+        println!(
+            "Watchpoint {:x} ({}): {} at {:04x} by {:02x}:{:04x}",
+            hit.index,
+            watchpoint.map_or(String::new(), |w| w.to_string()),
+            access,
+            hit.addr,
+            self.last_bank,
+            self.last_pc
+        );
+    }
+
+    fn print_location(&self, state: &GBState) {
+        self.print_instruction(state, state.cpu.pc);
+    }
+
+    /// Prints the instruction at `addr` and returns its length
+    fn print_instruction(&self, state: &GBState, addr: u16) -> u16 {
+        let instruction = disasm::disassemble(&state.mem, addr);
+        let bytes: Vec<String> = (0..instruction.length)
+            .map(|i| format!("{:02x}", state.mem.peek(addr.wrapping_add(i))))
+            .collect();
+
+        println!(
+            "{:02x}:{:04x}  {:<8}  {}",
+            current_bank(state, addr),
+            addr,
+            bytes.join(" "),
+            instruction
+        );
+
+        instruction.length
+    }
+
+    fn print_registers(&self, state: &GBState) {
+        let cpu = &state.cpu;
+        let f = cpu.r[reg::F as usize];
+        let flag = |bit: u8, name: char| if f & bit != 0 { name } else { '-' };
+
+        println!(
+            "A: {:02x}  F: {:02x}  [{}{}{}{}]",
+            cpu.r[reg::A as usize],
+            f,
+            flag(flag::ZF, 'Z'),
+            flag(flag::N, 'N'),
+            flag(flag::H, 'H'),
+            flag(flag::CY, 'C'),
+        );
+        println!(
+            "B: {:02x}  C: {:02x}  BC: {:04x}",
+            cpu.r[reg::B as usize],
+            cpu.r[reg::C as usize],
+            cpu.r16(reg::BC)
+        );
+        println!(
+            "D: {:02x}  E: {:02x}  DE: {:04x}",
+            cpu.r[reg::D as usize],
+            cpu.r[reg::E as usize],
+            cpu.r16(reg::DE)
+        );
+        println!(
+            "H: {:02x}  L: {:02x}  HL: {:04x}",
+            cpu.r[reg::H as usize],
+            cpu.r[reg::L as usize],
+            cpu.r16(reg::HL)
+        );
+        println!(
+            "SP: {:04x}  PC: {:04x}  ROM bank: {:02x}  IME: {}  HALT: {}",
+            cpu.sp,
+            cpu.pc,
+            state.mem.rom_bank(),
+            state.mem.ime as u8,
+            state.mem.halt as u8
+        );
+    }
+
+    fn dump_memory(&self, state: &GBState, addr: u16, len: u16) {
+        for line in (0..len).step_by(16) {
+            let start = addr.wrapping_add(line);
+            let bytes: Vec<String> = (0..16.min(len - line))
+                .map(|i| format!("{:02x}", state.mem.peek(start.wrapping_add(i))))
+                .collect();
+
+            println!("{:04x}: {}", start, bytes.join(" "));
+        }
+    }
+
+    fn prompt(&mut self, state: &mut GBState) -> DebuggerAction {
+        loop {
+            print!("(debug) ");
+            io::stdout().flush().ok();
+
+            let mut line = String::new();
+            let read = match self.input.as_mut() {
+                Some(input) => input.read_line(&mut line),
+                None => io::stdin().lock().read_line(&mut line),
+            };
+            if read.unwrap_or(0) == 0 {
+                println!("Input closed, detaching the debugger");
+                self.detached = true;
+                return DebuggerAction::Resume;
+            }
+
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_string(),
+            };
+            self.last_command = line.clone();
+
+            let mut args = line.split_whitespace();
+            let command = args.next().unwrap_or("");
+            let arg = args.next();
+
+            let opcode = state.mem.peek(state.cpu.pc);
+
+            match command {
+                "s" | "step" => match arg.map_or(Some(1), parse_hex::<u64>) {
+                    Some(n) if n > 0 => {
+                        self.mode = RunMode::Steps(n);
+                        return DebuggerAction::Resume;
+                    }
+                    _ => println!("Invalid number of steps"),
+                },
+                "n" | "next" => {
+                    let length = if CALL_OPCODES.contains(&opcode) {
+                        3
+                    } else if is_rst(opcode) {
+                        1
+                    } else {
+                        0
+                    };
+
+                    self.mode = if length == 0 {
+                        RunMode::Steps(1)
+                    } else {
+                        RunMode::Until {
+                            pc: state.cpu.pc.wrapping_add(length),
+                            sp: state.cpu.sp,
+                        }
+                    };
+                    return DebuggerAction::Resume;
+                }
+                "f" | "finish" => {
+                    self.mode = RunMode::Finish { sp: state.cpu.sp };
+                    return DebuggerAction::Resume;
+                }
+                "c" | "continue" => {
+                    self.mode = RunMode::Continue;
+                    return DebuggerAction::Resume;
+                }
+                "b" | "break" => match arg.and_then(Breakpoint::parse) {
+                    Some(breakpoint) => {
+                        println!(
+                            "Added breakpoint {:x} at {}",
+                            self.breakpoints.len(),
+                            breakpoint
+                        );
+                        self.breakpoints.push(breakpoint);
+                    }
+                    None => println!("Usage: break [bank:]addr"),
+                },
+                "d" | "delete" => match arg.and_then(parse_hex::<usize>) {
+                    Some(n) if n < self.breakpoints.len() => {
+                        self.breakpoints.remove(n);
+                    }
+                    _ => println!("Usage: delete <n>, see breakpoints"),
+                },
+                "bl" | "breakpoints" => {
+                    for (i, breakpoint) in self.breakpoints.iter().enumerate() {
+                        println!("{:x}: {}", i, breakpoint);
+                    }
+                }
+                "watch" | "rwatch" | "cwatch" => {
+                    let kind = match command {
+                        "rwatch" => WatchKind::Read,
+                        "cwatch" => WatchKind::Change,
+                        _ => WatchKind::Write,
+                    };
+
+                    match arg.and_then(|range| Watchpoint::parse(kind, range, args.next())) {
+                        Some(watchpoint) => {
+                            println!(
+                                "Added watchpoint {:x} on {}",
+                                state.mem.watchpoints.len(),
+                                watchpoint
+                            );
+                            state.mem.watchpoints.push(watchpoint);
+                        }
+                        None => println!("Usage: {} <addr>[-<end>] [value]", command),
+                    }
+                }
+                "unwatch" => match arg.and_then(parse_hex::<usize>) {
+                    Some(n) if n < state.mem.watchpoints.len() => {
+                        state.mem.watchpoints.remove(n);
+                    }
+                    _ => println!("Usage: unwatch <n>, see watchpoints"),
+                },
+                "wl" | "watchpoints" => {
+                    for (i, watchpoint) in state.mem.watchpoints.iter().enumerate() {
+                        println!("{:x}: {}", i, watchpoint);
+                    }
+                }
+                "r" | "regs" => self.print_registers(state),
+                "l" | "disasm" => {
+                    let addr = arg.map_or(Some(state.cpu.pc), parse_hex::<u16>);
+                    let count = args.next().map_or(Some(10), parse_hex::<u16>);
+
+                    match (addr, count) {
+                        (Some(mut addr), Some(count)) => {
+                            for _ in 0..count {
+                                addr = addr.wrapping_add(self.print_instruction(state, addr));
+                            }
+                        }
+                        _ => println!("Usage: disasm [addr] [count]"),
+                    }
+                }
+                "x" | "mem" => {
+                    let addr = arg.and_then(parse_hex::<u16>);
+                    let len = args.next().map_or(Some(0x40), parse_hex::<u16>);
+
+                    match (addr, len) {
+                        (Some(addr), Some(len)) => self.dump_memory(state, addr, len),
+                        _ => println!("Usage: mem <addr> [len]"),
+                    }
+                }
+                "detach" => {
+                    self.detached = true;
+                    return DebuggerAction::Resume;
+                }
+                "q" | "quit" => return DebuggerAction::Quit,
+                "h" | "help" => println!("{}", HELP),
+                _ => println!("Unknown command {:?}, type help for the list", command),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+    use crate::error::EmuError;
+    use crate::state::Memory;
+    use std::io::Cursor;
+
+    #[test]
+    fn parse_breakpoints() {
+        let parse = |s| Breakpoint::parse(s).map(|b| (b.bank, b.addr));
+
+        assert_eq!(parse("4a30"), Some((None, 0x4a30)));
+        assert_eq!(parse("0x150"), Some((None, 0x150)));
+        assert_eq!(parse("02:4a30"), Some((Some(2), 0x4a30)));
+        assert_eq!(parse("1f:$7fff"), Some((Some(0x1f), 0x7fff)));
+
+        for s in ["", "main", "10000", "2:", ":4000", "2:4000:1", "-1"] {
+            assert_eq!(parse(s), None, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn parse_watchpoints() {
+        let w = Watchpoint::parse(WatchKind::Write, "c000-c0ff", None).unwrap();
+        assert_eq!((w.start, w.end, w.value), (0xc000, 0xc0ff, None));
+        assert!(w.contains(0xc000) && w.contains(0xc080) && w.contains(0xc0ff));
+        assert!(!w.contains(0xbfff) && !w.contains(0xc100));
+
+        let w = Watchpoint::parse(WatchKind::Read, "ff44", Some("90")).unwrap();
+        assert_eq!((w.start, w.end, w.value), (0xff44, 0xff44, Some(0x90)));
+        assert!(w.contains(0xff44) && !w.contains(0xff45));
+
+        for (range, value) in [
+            ("", None),
+            ("c100-c000", None),
+            ("c000-", None),
+            ("c000-c0ff-c1ff", None),
+            ("10000", None),
+            ("c000", Some("100")),
+            ("c000", Some("x")),
+        ] {
+            assert_eq!(
+                Watchpoint::parse(WatchKind::Write, range, value),
+                None,
+                "{:?} {:?}",
+                range,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn watchpoint_triggers() {
+        let watch = |kind, value| Watchpoint::parse(kind, "c000", value).unwrap();
+
+        let read = watch(WatchKind::Read, None);
+        assert!(read.triggers(false, 0x12, 0x12));
+        assert!(!read.triggers(true, 0x12, 0x34));
+
+        let write = watch(WatchKind::Write, None);
+        assert!(write.triggers(true, 0x12, 0x34));
+        assert!(write.triggers(true, 0x12, 0x12));
+        assert!(!write.triggers(false, 0x12, 0x12));
+
+        let change = watch(WatchKind::Change, None);
+        assert!(change.triggers(true, 0x12, 0x34));
+        assert!(!change.triggers(true, 0x12, 0x12));
+        assert!(!change.triggers(false, 0x12, 0x12));
+
+        // The value filter applies to the value read or written
+        let read = watch(WatchKind::Read, Some("90"));
+        assert!(read.triggers(false, 0x90, 0x90));
+        assert!(!read.triggers(false, 0x91, 0x91));
+
+        let write = watch(WatchKind::Write, Some("ff"));
+        assert!(write.triggers(true, 0x00, 0xff));
+        assert!(!write.triggers(true, 0xff, 0x00));
+
+        let change = watch(WatchKind::Change, Some("ff"));
+        assert!(change.triggers(true, 0x00, 0xff));
+        assert!(!change.triggers(true, 0xff, 0xff));
+    }
+
+    #[test]
+    fn io_watchpoint() {
+        let mut mem = Memory::new();
+        mem.w_io(0x42, 0x12);
+        mem.watchpoints = vec![
+            Watchpoint::parse(WatchKind::Write, "ff42", None).unwrap(),
+            Watchpoint::parse(WatchKind::Change, "ff42", None).unwrap(),
+        ];
+
+        mem.w_io(0x42, 0x34);
+        let hits = mem.take_watchpoint_hits();
+        assert_eq!(hits.len(), 2);
+        for (index, hit) in hits.iter().enumerate() {
+            assert_eq!(hit.index, index);
+            assert_eq!((hit.addr, hit.old, hit.new), (0xff42, 0x12, 0x34));
+        }
+
+        // Same value, only the write watchpoint triggers
+        mem.w_io(0x42, 0x34);
+        let hits = mem.take_watchpoint_hits();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(
+            (hits[0].kind, hits[0].old, hits[0].new),
+            (WatchKind::Write, 0x34, 0x34)
+        );
+    }
+
+    // NOP, NOP, CALL 0xc010, NOP, JR to itself and at 0xc010 NOP, NOP, RET
+    const PROGRAM: [(u16, &[u8]); 2] = [
+        (0xc000, &[0x00, 0x00, 0xcd, 0x10, 0xc0, 0x00, 0x18, 0xfe]),
+        (0xc010, &[0x00, 0x00, 0xc9]),
+    ];
+
+    /// Runs the program with a breakpoint on the CALL and the commands given,
+    /// returns PC when they end with `quit`
+    fn run(commands: &str) -> Option<u16> {
+        let mut emulator = Emulator::new();
+
+        for (addr, bytes) in PROGRAM {
+            for (i, &byte) in bytes.iter().enumerate() {
+                emulator.state.mem.w(addr + i as u16, byte);
+            }
+        }
+        emulator.state.cpu.pc = 0xc000;
+        emulator.state.cpu.sp = 0xdff0;
+
+        let mut debugger = Debugger::with_input(Box::new(Cursor::new(commands.to_string())));
+        debugger
+            .breakpoints
+            .push(Breakpoint::parse("c002").unwrap());
+        emulator.debugger = Some(debugger);
+
+        for _ in 0..1000 {
+            match emulator.step_instruction() {
+                Ok(_) => {}
+                Err(EmuError::Quit) => return Some(emulator.state.cpu.pc),
+                Err(e) => panic!("{}", e),
+            }
+        }
+
+        None
+    }
+
+    #[test]
+    fn breakpoint() {
+        assert_eq!(run("q"), Some(0xc002));
+        assert_eq!(run("b c011\nc\nq"), Some(0xc011));
+        // Nothing left to stop on
+        assert_eq!(run("d 0\nc\nq"), None);
+        // The input ends, the debugger detaches
+        assert_eq!(run("regs\n"), None);
+    }
+
+    #[test]
+    fn step() {
+        assert_eq!(run("s\nq"), Some(0xc010));
+        assert_eq!(run("s 2\nq"), Some(0xc011));
+        // An empty line repeats the step
+        assert_eq!(run("s\n\nq"), Some(0xc011));
+    }
+
+    #[test]
+    fn next_and_finish() {
+        assert_eq!(run("n\nq"), Some(0xc005));
+        assert_eq!(run("s\nf\nq"), Some(0xc005));
+        // NOP isn't a call, next is a single step
+        assert_eq!(run("s\nn\nq"), Some(0xc011));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::state::Memory;
+use std::fmt;
+
+// Operand names, indexed like the register numbers of the opcodes (see
+// `state::reg`)
+const R8: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const R16: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const R16_STACK: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const R16_MEM: [&str; 4] = ["(BC)", "(DE)", "(HL+)", "(HL-)"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+
+const ALU: [&str; 8] = [
+    "ADD A,", "ADC A,", "SUB", "SBC A,", "AND", "XOR", "OR", "CP",
+];
+const ROTATIONS_A: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+const ROTATIONS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+/// A decoded instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    // In bytes, including the opcode and the CB prefix
+    pub length: u16,
+    pub text: String,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Parses a hexadecimal number, with an optional 0x or $ prefix
+pub fn parse_hex<T: TryFrom<u64>>(s: &str) -> Option<T> {
+    let s = s.trim_start_matches("0x").trim_start_matches('$');
+
+    u64::from_str_radix(s, 16)
+        .ok()
+        .and_then(|v| T::try_from(v).ok())
+}
+
+/// Parses an address with an optional ROM bank, `[bank:]addr`
+pub fn parse_location(s: &str) -> Option<(Option<usize>, u16)> {
+    match s.split_once(':') {
+        Some((bank, addr)) => Some((Some(parse_hex(bank)?), parse_hex(addr)?)),
+        None => Some((None, parse_hex(s)?)),
+    }
+}
+
+fn signed(n: u8) -> String {
+    if n & 0x80 != 0 {
+        format!("-${:02x}", (n as i8).unsigned_abs())
+    } else {
+        format!("+${:02x}", n)
+    }
+}
+
+/// Decodes the instruction at `addr` from its bytes, `bytes[0]` being the
+/// opcode. Missing operand bytes are read as 0.
+pub fn decode(bytes: &[u8], addr: u16) -> Instruction {
+    let byte = |i: usize| bytes.get(i).copied().unwrap_or(0);
+
+    let opcode = byte(0);
+    let n = byte(1);
+    let nn = u16::from_le_bytes([byte(1), byte(2)]);
+
+    let n1 = (opcode >> 3) & 0b111;
+    let n2 = opcode & 0b111;
+
+    let (length, text) = match opcode >> 6 {
+        0b00 => match n2 {
+            0b000 => match n1 {
+                0b000 => (1, "NOP".to_string()),
+                0b001 => (3, format!("LD (${:04x}), SP", nn)),
+                0b010 => (2, "STOP".to_string()),
+                0b011 => (
+                    2,
+                    format!(
+                        "JR ${:04x}",
+                        addr.wrapping_add(2).wrapping_add(n as i8 as u16)
+                    ),
+                ),
+                _ => (
+                    2,
+                    format!(
+                        "JR {}, ${:04x}",
+                        CONDITIONS[(n1 & 0b11) as usize],
+                        addr.wrapping_add(2).wrapping_add(n as i8 as u16)
+                    ),
+                ),
+            },
+            0b001 => {
+                if n1 & 1 == 1 {
+                    (1, format!("ADD HL, {}", R16[(n1 >> 1) as usize]))
+                } else {
+                    (3, format!("LD {}, ${:04x}", R16[(n1 >> 1) as usize], nn))
+                }
+            }
+            0b010 => {
+                if n1 & 1 == 1 {
+                    (1, format!("LD A, {}", R16_MEM[(n1 >> 1) as usize]))
+                } else {
+                    (1, format!("LD {}, A", R16_MEM[(n1 >> 1) as usize]))
+                }
+            }
+            0b011 => {
+                if n1 & 1 == 1 {
+                    (1, format!("DEC {}", R16[(n1 >> 1) as usize]))
+                } else {
+                    (1, format!("INC {}", R16[(n1 >> 1) as usize]))
+                }
+            }
+            0b100 => (1, format!("INC {}", R8[n1 as usize])),
+            0b101 => (1, format!("DEC {}", R8[n1 as usize])),
+            0b110 => (2, format!("LD {}, ${:02x}", R8[n1 as usize], n)),
+            _ => (1, ROTATIONS_A[n1 as usize].to_string()),
+        },
+        0b01 => {
+            if n1 == 0b110 && n2 == 0b110 {
+                (1, "HALT".to_string())
+            } else {
+                (1, format!("LD {}, {}", R8[n1 as usize], R8[n2 as usize]))
+            }
+        }
+        0b10 => (1, format!("{} {}", ALU[n1 as usize], R8[n2 as usize])),
+        _ => match n2 {
+            0b000 => match n1 {
+                0b100 => (2, format!("LDH (${:04x}), A", 0xff00 | n as u16)),
+                0b101 => (2, format!("ADD SP, {}", signed(n))),
+                0b110 => (2, format!("LDH A, (${:04x})", 0xff00 | n as u16)),
+                0b111 => (2, format!("LD HL, SP{}", signed(n))),
+                _ => (1, format!("RET {}", CONDITIONS[n1 as usize])),
+            },
+            0b001 => match n1 {
+                0b001 => (1, "RET".to_string()),
+                0b011 => (1, "RETI".to_string()),
+                0b101 => (1, "JP HL".to_string()),
+                0b111 => (1, "LD SP, HL".to_string()),
+                _ => (1, format!("POP {}", R16_STACK[(n1 >> 1) as usize])),
+            },
+            0b010 => match n1 {
+                0b100 => (1, "LD ($ff00+C), A".to_string()),
+                0b101 => (3, format!("LD (${:04x}), A", nn)),
+                0b110 => (1, "LD A, ($ff00+C)".to_string()),
+                0b111 => (3, format!("LD A, (${:04x})", nn)),
+                _ => (3, format!("JP {}, ${:04x}", CONDITIONS[n1 as usize], nn)),
+            },
+            0b011 => match n1 {
+                0b000 => (3, format!("JP ${:04x}", nn)),
+                0b001 => (2, decode_cb(n)),
+                0b110 => (1, "DI".to_string()),
+                0b111 => (1, "EI".to_string()),
+                _ => (1, format!("ILLEGAL ${:02x}", opcode)),
+            },
+            0b100 => match n1 {
+                0b000..=0b011 => (3, format!("CALL {}, ${:04x}", CONDITIONS[n1 as usize], nn)),
+                _ => (1, format!("ILLEGAL ${:02x}", opcode)),
+            },
+            0b101 => match n1 {
+                0b001 => (3, format!("CALL ${:04x}", nn)),
+                0b011 | 0b101 | 0b111 => (1, format!("ILLEGAL ${:02x}", opcode)),
+                _ => (1, format!("PUSH {}", R16_STACK[(n1 >> 1) as usize])),
+            },
+            0b110 => (2, format!("{} ${:02x}", ALU[n1 as usize], n)),
+            _ => (1, format!("RST ${:02x}", n1 << 3)),
+        },
+    };
+
+    Instruction { length, text }
+}
+
+// Instructions following the 0xcb prefix, see `opcodes::op_bitwise`
+fn decode_cb(opcode: u8) -> String {
+    let n1 = (opcode >> 3) & 0b111;
+    let r = R8[(opcode & 0b111) as usize];
+
+    match opcode >> 6 {
+        0b00 => format!("{} {}", ROTATIONS[n1 as usize], r),
+        0b01 => format!("BIT {}, {}", n1, r),
+        0b10 => format!("RES {}, {}", n1, r),
+        _ => format!("SET {}, {}", n1, r),
+    }
+}
+
+/// Decodes the instruction at `addr` in the current memory mapping
+pub fn disassemble(mem: &Memory, addr: u16) -> Instruction {
+    let bytes: Vec<u8> = (0..3).map(|i| mem.peek(addr.wrapping_add(i))).collect();
+
+    decode(&bytes, addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(bytes: &[u8], addr: u16) -> (u16, String) {
+        let instruction = decode(bytes, addr);
+        (instruction.length, instruction.text)
+    }
+
+    #[test]
+    fn instructions() {
+        let cases: &[(&[u8], u16, u16, &str)] = &[
+            (&[0x00], 0x0100, 1, "NOP"),
+            (&[0x08, 0x34, 0x12], 0x0100, 3, "LD ($1234), SP"),
+            (&[0x18, 0x05], 0x0150, 2, "JR $0157"),
+            (&[0x18, 0xfe], 0x0150, 2, "JR $0150"),
+            (&[0x20, 0x80], 0x0150, 2, "JR NZ, $00d2"),
+            (&[0x38, 0xfc], 0x0001, 2, "JR C, $ffff"),
+            (&[0x18, 0x10], 0xfff8, 2, "JR $000a"),
+            (&[0xe0, 0x40], 0x0100, 2, "LDH ($ff40), A"),
+            (&[0xf0, 0x44], 0x0100, 2, "LDH A, ($ff44)"),
+            (&[0xe2], 0x0100, 1, "LD ($ff00+C), A"),
+            (&[0xe8, 0x08], 0x0100, 2, "ADD SP, +$08"),
+            (&[0xe8, 0xf8], 0x0100, 2, "ADD SP, -$08"),
+            (&[0xf8, 0x80], 0x0100, 2, "LD HL, SP-$80"),
+            (&[0xf8, 0x7f], 0x0100, 2, "LD HL, SP+$7f"),
+            (&[0xc3, 0x50, 0x01], 0x0100, 3, "JP $0150"),
+            (&[0xc2, 0x00, 0x40], 0x0100, 3, "JP NZ, $4000"),
+            (&[0xda, 0xff, 0x7f], 0x0100, 3, "JP C, $7fff"),
+            (&[0xcd, 0x34, 0x12], 0x0100, 3, "CALL $1234"),
+            (&[0xcc, 0x34, 0x12], 0x0100, 3, "CALL Z, $1234"),
+            (&[0xd4, 0x34, 0x12], 0x0100, 3, "CALL NC, $1234"),
+            (&[0xc0], 0x0100, 1, "RET NZ"),
+            (&[0xd9], 0x0100, 1, "RETI"),
+            (&[0xc7], 0x0100, 1, "RST $00"),
+            (&[0xef], 0x0100, 1, "RST $28"),
+            (&[0xff], 0x0100, 1, "RST $38"),
+            (&[0x76], 0x0100, 1, "HALT"),
+            (&[0x7e], 0x0100, 1, "LD A, (HL)"),
+            (&[0x3a], 0x0100, 1, "LD A, (HL-)"),
+            (&[0xfe, 0x90], 0x0100, 2, "CP $90"),
+            (&[0xce, 0x01], 0x0100, 2, "ADC A, $01"),
+            (&[0x10, 0x00], 0x0100, 2, "STOP"),
+            // Missing operand bytes are read as 0
+            (&[0x31], 0x0100, 3, "LD SP, $0000"),
+        ];
+
+        for &(bytes, addr, length, expected) in cases {
+            assert_eq!(
+                text(bytes, addr),
+                (length, expected.to_string()),
+                "{:02x?} at {:04x}",
+                bytes,
+                addr
+            );
+        }
+    }
+
+    #[test]
+    fn illegal_opcodes() {
+        for opcode in [
+            0xd3, 0xdb, 0xdd, 0xe3, 0xe4, 0xeb, 0xec, 0xed, 0xf4, 0xfc, 0xfd,
+        ] {
+            assert_eq!(
+                text(&[opcode, 0x12, 0x34], 0x0100),
+                (1, format!("ILLEGAL ${:02x}", opcode))
+            );
+        }
+
+        let illegal = (0..=0xff)
+            .filter(|&opcode| decode(&[opcode], 0).text.starts_with("ILLEGAL"))
+            .count();
+        assert_eq!(illegal, 11);
+    }
+
+    #[test]
+    fn cb_instructions() {
+        let registers = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+        let rows = [
+            "RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL", "BIT 0,", "BIT 1,", "BIT 2,",
+            "BIT 3,", "BIT 4,", "BIT 5,", "BIT 6,", "BIT 7,", "RES 0,", "RES 1,", "RES 2,",
+            "RES 3,", "RES 4,", "RES 5,", "RES 6,", "RES 7,", "SET 0,", "SET 1,", "SET 2,",
+            "SET 3,", "SET 4,", "SET 5,", "SET 6,", "SET 7,",
+        ];
+
+        for (row, mnemonic) in rows.iter().enumerate() {
+            for (i, register) in registers.iter().enumerate() {
+                let opcode = (row << 3 | i) as u8;
+                assert_eq!(
+                    text(&[0xcb, opcode], 0x0100),
+                    (2, format!("{} {}", mnemonic, register)),
+                    "CB {:02x}",
+                    opcode
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn locations() {
+        assert_eq!(parse_location("3:4000"), Some((Some(3), 0x4000)));
+        assert_eq!(parse_location("$150"), Some((None, 0x150)));
+        assert_eq!(parse_location("0x7fff"), Some((None, 0x7fff)));
+        assert_eq!(parse_location("1f:$4a30"), Some((Some(0x1f), 0x4a30)));
+
+        for s in [
+            "", "start", "10000", "3:", ":4000", "3:4000:1", "-150", "3:g000",
+        ] {
+            assert_eq!(parse_location(s), None, "{:?}", s);
+        }
+    }
+}
@@ -1,9 +1,6 @@
-use crate::consts::DISPLAY_UPDATE_SLEEP_TIME_MICROS;
-use crate::state::MemError;
-use minifb::{Window, WindowOptions};
-use std::time::SystemTime;
+use crate::savestate::{Savable, SaveStateError, StateReader, StateWriter};
 
-const COLORS: [u32; 4] = [0x00e0f8d0, 0x0088c070, 0x346856, 0x00081820];
+pub const COLORS: [u32; 4] = [0x00e0f8d0, 0x0088c070, 0x346856, 0x00081820];
 
 const LINE_DOTS: u64 = 456;
 
@@ -27,7 +24,6 @@ pub enum DisplayInterrupt {
 
 #[derive(Debug)]
 pub struct Display {
-    window: Window,
     framebuffer: [u32; 160 * 144],
     bg_buffer: [u8; 160 * 144],
 
@@ -52,21 +48,21 @@ pub struct Display {
     pub window_x: u8,
     pub window_y: u8,
 
-    last_dt: SystemTime,
-
     pub stat: u64,
+
+    // Number of frames completed since power on, incremented on each VBlank
+    pub frames: u64,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Display {
     pub fn new() -> Self {
         Self {
-            window: Window::new(
-                "Gameboy Emulator",
-                /*512, 461*/ 1200,
-                1080,
-                WindowOptions::default(),
-            )
-            .unwrap(),
             framebuffer: [0; 160 * 144],
             bg_buffer: [0; 160 * 144],
             tiledata: [0; 0x3000],
@@ -83,8 +79,8 @@ impl Display {
             ly: 0,
             window_x: 0,
             window_y: 0,
-            last_dt: SystemTime::now(),
             stat: 0,
+            frames: 0,
             lyc: 0,
             cgb_mode: false,
             lcd_interrupt_mode: 0xff,
@@ -95,10 +91,12 @@ impl Display {
         self.framebuffer = [COLORS[0]; 160 * 144];
     }
 
-    pub fn update(&mut self) {
-        self.window
-            .update_with_buffer(&self.framebuffer, 160, 144)
-            .unwrap();
+    pub fn framebuffer(&self) -> &[u32; 160 * 144] {
+        &self.framebuffer
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & lcdc_flags::LCD_ENABLE != 0
     }
 
     pub fn color_palette(&self, color_byte: u8, palette: u8, cgb_mode: bool) -> u32 {
@@ -125,10 +123,10 @@ impl Display {
         } + if bg_map_attr & 0b1000 != 0 { 0x1800 } else { 0 };
 
         for b in (0..8).rev() {
-            let data = (((self.tiledata[tile_pointer + l * 2] as u8) >> b) & 1)
-                | ((((self.tiledata[tile_pointer + l * 2 + 1] as u8) >> b) & 1) << 1);
+            let data = ((self.tiledata[tile_pointer + l * 2] >> b) & 1)
+                | (((self.tiledata[tile_pointer + l * 2 + 1] >> b) & 1) << 1);
 
-            let pxx = (x as i32 + 7 - b as i32) as u8;
+            let pxx = (x as i32 + 7 - b) as u8;
             let pxy = y as i32;
 
             if pxy < 144 && pxx < 160 {
@@ -153,7 +151,7 @@ impl Display {
         }
     }
 
-    pub fn w(&mut self, addr: u16, value: u8) -> Result<(), MemError> {
+    pub fn w(&mut self, addr: u16, value: u8) {
         if self.vram_bank == 0 {
             if addr < 0x1800 {
                 self.tiledata[addr as usize] = value;
@@ -169,25 +167,24 @@ impl Display {
                 self.bg_map_attr[addr as usize - 0x1800] = value;
             }
         }
-        Ok(())
     }
 
-    pub fn r(&self, addr: u16) -> Result<u8, MemError> {
+    pub fn r(&self, addr: u16) -> u8 {
         if self.vram_bank == 0 {
             if addr < 0x1800 {
-                Ok(self.tiledata[addr as usize])
+                self.tiledata[addr as usize]
             } else if addr >= 0x7e00 {
-                Ok(self.oam[addr as usize - 0x7e00])
+                self.oam[addr as usize - 0x7e00]
             } else {
-                Ok(self.tilemaps[addr as usize - 0x1800])
+                self.tilemaps[addr as usize - 0x1800]
             }
         } else {
             if addr < 0x1800 {
-                Ok(self.tiledata[addr as usize + 0x1800])
+                self.tiledata[addr as usize + 0x1800]
             } else if addr < 0x1c00 {
-                Ok(self.bg_map_attr[addr as usize - 0x1800])
+                self.bg_map_attr[addr as usize - 0x1800]
             } else {
-                Ok(0)
+                0
             }
         }
     }
@@ -208,7 +205,7 @@ impl Display {
                 tile,
                 x as u8 * 8 - self.viewport_x,
                 self.ly,
-                (y_tile % 8) as usize,
+                y_tile % 8,
                 bg_map_attr,
             );
         }
@@ -237,7 +234,7 @@ impl Display {
                     tile,
                     x as u8 * 8 + self.window_x - 7,
                     self.ly,
-                    (y_tile % 8) as usize,
+                    y_tile % 8,
                     0,
                 );
             }
@@ -284,30 +281,32 @@ impl Display {
 
             for b in 0..8 {
                 let pxx = if x_flip {
-                    x as i32 + b as i32 - 8 as u8 as i32
+                    x as i32 + b - 8_u8 as i32
                 } else {
-                    x as i32 + 7 - b as i32 - 8 as u8 as i32
+                    x as i32 + 7 - b - 8_u8 as i32
                 };
                 let pxy = self.ly as i32;
 
-                let data = (((self.tiledata[tile_pointer + l as usize * 2] as u8) >> b) & 1)
-                    | ((((self.tiledata[tile_pointer + l as usize * 2 + 1] as u8) >> b) & 1) << 1);
-
-                if pxy < 144 && pxx < 160 && pxy >= 0 && pxx >= 0 {
-                    if data != 0
-                        && !((bg_priority_flag/* && self.lcdc & lcdc_flags::BG_PRIORITY != 0 */)
-                            && self.bg_buffer[pxy as usize * 160 + pxx as usize] != 0)
-                    {
-                        self.framebuffer[pxy as usize * 160 + pxx as usize] = self.color_palette(
-                            data,
-                            if self.cgb_mode {
-                                cgb_palette + 8
-                            } else {
-                                self.obj_palettes[palette as usize]
-                            },
-                            self.cgb_mode,
-                        );
-                    }
+                let data = ((self.tiledata[tile_pointer + l as usize * 2] >> b) & 1)
+                    | (((self.tiledata[tile_pointer + l as usize * 2 + 1] >> b) & 1) << 1);
+
+                if pxy < 144
+                    && pxx < 160
+                    && pxy >= 0
+                    && pxx >= 0
+                    && data != 0
+                    && !((bg_priority_flag/* && self.lcdc & lcdc_flags::BG_PRIORITY != 0 */)
+                        && self.bg_buffer[pxy as usize * 160 + pxx as usize] != 0)
+                {
+                    self.framebuffer[pxy as usize * 160 + pxx as usize] = self.color_palette(
+                        data,
+                        if self.cgb_mode {
+                            cgb_palette + 8
+                        } else {
+                            self.obj_palettes[palette as usize]
+                        },
+                        self.cgb_mode,
+                    );
                 }
             }
         }
@@ -327,15 +326,7 @@ impl Display {
                 if self.lcd_interrupt_mode == 1 {
                     ret_interrupt = DisplayInterrupt::Both;
                 }
-                if SystemTime::now()
-                    .duration_since(self.last_dt)
-                    .unwrap()
-                    .as_micros()
-                    > DISPLAY_UPDATE_SLEEP_TIME_MICROS as u128
-                {
-                    self.update();
-                    self.last_dt = SystemTime::now();
-                }
+                self.frames += 1;
             }
             if self.ly < 0x90 && (self.lcd_interrupt_mode == 0 || self.lcd_interrupt_mode == 2) {
                 ret_interrupt = DisplayInterrupt::Stat;
@@ -349,6 +340,61 @@ impl Display {
             self.ly = 0;
         }
 
-        return ret_interrupt;
+        ret_interrupt
+    }
+}
+
+impl Savable for Display {
+    fn save(&self, w: &mut StateWriter) {
+        for pixel in self.framebuffer {
+            w.u32(pixel);
+        }
+        w.bytes(&self.bg_buffer);
+        w.bytes(&self.tiledata);
+        w.bytes(&self.bg_map_attr);
+        w.bytes(&self.tilemaps);
+        w.bytes(&self.oam);
+        w.bytes(&self.cram);
+        w.u8(self.bg_palette);
+        w.bytes(&self.obj_palettes);
+        w.u8(self.viewport_y);
+        w.u8(self.viewport_x);
+        w.u8(self.lcdc);
+        w.u8(self.ly);
+        w.u8(self.lyc);
+        w.u8(self.lcd_interrupt_mode);
+        w.u8(self.vram_bank);
+        w.bool(self.cgb_mode);
+        w.u8(self.window_x);
+        w.u8(self.window_y);
+        w.u64(self.stat);
+    }
+
+    // `frames` counts the frames emulated in this session and isn't restored
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        for pixel in self.framebuffer.iter_mut() {
+            *pixel = r.u32()?;
+        }
+        r.bytes(&mut self.bg_buffer)?;
+        r.bytes(&mut self.tiledata)?;
+        r.bytes(&mut self.bg_map_attr)?;
+        r.bytes(&mut self.tilemaps)?;
+        r.bytes(&mut self.oam)?;
+        r.bytes(&mut self.cram)?;
+        self.bg_palette = r.u8()?;
+        r.bytes(&mut self.obj_palettes)?;
+        self.viewport_y = r.u8()?;
+        self.viewport_x = r.u8()?;
+        self.lcdc = r.u8()?;
+        self.ly = r.u8()?;
+        self.lyc = r.u8()?;
+        self.lcd_interrupt_mode = r.u8()?;
+        self.vram_bank = r.u8()? & 1;
+        self.cgb_mode = r.bool()?;
+        self.window_x = r.u8()?;
+        self.window_y = r.u8()?;
+        self.stat = r.u64()?;
+
+        Ok(())
     }
 }
@@ -0,0 +1,386 @@
+use crate::consts::CYCLES_PER_FRAME;
+use crate::debugger::{Debugger, DebuggerAction};
+use crate::error::EmuError;
+use crate::opcodes;
+use crate::savestate::{Savable, SaveStateError, StateReader, StateWriter};
+use crate::screenshot::Image;
+use crate::serial::SerialDevice;
+use crate::state::{Event, GBState};
+use crate::trace::Tracer;
+
+/// Frontend-agnostic handle on a running Gameboy.
+///
+/// It owns the whole machine state and only knows how to advance it: drawing
+/// the framebuffer, playing the audio samples and feeding the joypad are left
+/// to whoever embeds it.
+pub struct Emulator {
+    pub state: GBState,
+    // Checked before each instruction when attached
+    pub debugger: Option<Debugger>,
+    // Logs every instruction when set
+    pub tracer: Option<Tracer>,
+
+    // See `frames`, not saved in the states
+    frames: u64,
+    frame_cycles: u64,
+    display_frames: u64,
+}
+
+impl Emulator {
+    /// Nothing in the core needs a display, an audio device or a gamepad, it
+    /// also runs headless (e.g. on a CI server). At most one second of audio
+    /// samples is kept around when `audio_samples` isn't called.
+    pub fn new() -> Self {
+        Self {
+            state: GBState::new(),
+            debugger: None,
+            tracer: None,
+
+            frames: 0,
+            frame_cycles: 0,
+            display_frames: 0,
+        }
+    }
+
+    /// Number of frames run since the emulator was created. With the LCD off
+    /// or in STOP mode no VBlank ever comes, the time a frame would have
+    /// taken counts as one.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    fn count_frames(&mut self, c: u64) {
+        let display = &self.state.mem.display;
+
+        self.frame_cycles += c;
+        if display.frames != self.display_frames {
+            self.display_frames = display.frames;
+        } else if !((!display.lcd_enabled() || self.state.mem.stopped)
+            && self.frame_cycles >= CYCLES_PER_FRAME)
+        {
+            return;
+        }
+
+        self.frames += 1;
+        self.frame_cycles = 0;
+    }
+
+    /// Attaches the debugger if needed and stops before the next instruction
+    pub fn break_into_debugger(&mut self) {
+        self.debugger.get_or_insert_with(Debugger::new).break_now();
+    }
+
+    pub fn load_rom(&mut self, file: &str) -> Result<(), EmuError> {
+        Ok(self.state.mem.load_rom(file)?)
+    }
+
+    /// Title and global checksum of the loaded cartridge, saved in the states
+    /// to refuse loading them with another game.
+    fn rom_identity(&self) -> (String, u16) {
+        match &self.state.mem.header {
+            Some(header) => (header.title.clone(), header.global_checksum),
+            None => (String::new(), 0),
+        }
+    }
+
+    /// Snapshots the whole machine (CPU, memory, banking, display, timers and
+    /// audio channels) in the versioned save state format. The cartridge ROM
+    /// isn't included, the state can only be loaded back with the same one.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        let (title, global_checksum) = self.rom_identity();
+
+        w.string(&title);
+        w.u16(global_checksum);
+        self.state.save(&mut w);
+
+        w.into_bytes()
+    }
+
+    /// Checks that the state was made with the loaded cartridge, returns a
+    /// reader on the machine state following the identity
+    fn state_reader<'a>(&self, data: &'a [u8]) -> Result<StateReader<'a>, SaveStateError> {
+        let mut r = StateReader::new(data)?;
+        let (title, global_checksum) = self.rom_identity();
+
+        let state_title = r.string()?;
+        let state_global_checksum = r.u16()?;
+        if state_title != title || state_global_checksum != global_checksum {
+            return Err(SaveStateError::WrongRom {
+                title: state_title,
+                global_checksum: state_global_checksum,
+            });
+        }
+
+        Ok(r)
+    }
+
+    /// Restores a state made by `save_state`. The emulator is left untouched
+    /// when it fails: the state is decoded in a scratch machine first, and
+    /// only loaded once it is known to be valid.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut scratch = self.state.scratch();
+        let mut r = self.state_reader(data)?;
+        scratch.load(&mut r)?;
+        r.finish()?;
+
+        let mut r = self.state_reader(data)?;
+        self.state.load(&mut r)?;
+        r.finish()
+    }
+
+    pub fn save_state_file(&self, file: &str) -> Result<(), SaveStateError> {
+        std::fs::write(file, self.save_state())?;
+
+        Ok(())
+    }
+
+    pub fn load_state_file(&mut self, file: &str) -> Result<(), SaveStateError> {
+        self.load_state(&std::fs::read(file)?)
+    }
+
+    /// Executes a single instruction (or a 4 cycles idle step when halted)
+    /// and the interrupt dispatch following it. The timers, the display and
+    /// the audio are advanced along, one M-cycle at a time as the CPU
+    /// accesses the memory. Returns the number of cycles elapsed, counted at
+    /// the normal speed clock even in CGB double speed.
+    ///
+    /// An error doesn't interrupt the step: the machine is left in a
+    /// consistent state and the next call carries on from there.
+    pub fn step_instruction(&mut self) -> Result<u64, EmuError> {
+        if self.state.mem.stopped {
+            // Any button wakes the CPU up, everything else is frozen
+            if self.state.mem.joypad_reg != 0xff {
+                self.state.mem.stopped = false;
+            }
+
+            self.count_frames(4);
+            return Ok(4);
+        }
+
+        // Still checked once locked up, to inspect the machine after an
+        // illegal opcode
+        if !self.state.mem.halt {
+            if let Some(debugger) = self.debugger.as_mut() {
+                if debugger.before_instruction(&mut self.state) == DebuggerAction::Quit {
+                    return Err(EmuError::Quit);
+                }
+            }
+        }
+
+        self.state.step_cycles = 0;
+
+        let c = if !self.state.mem.halt && !self.state.mem.locked {
+            if let Some(tracer) = self.tracer.as_mut() {
+                if let Err(e) = tracer.before_instruction(&self.state) {
+                    // Stopped for good, the error is returned only once
+                    self.tracer = None;
+                    self.state.mem.raise(EmuError::Io(e));
+                }
+            }
+
+            opcodes::exec_opcode(&mut self.state)
+        } else {
+            4
+        };
+
+        // The cycles of the instruction that weren't spent accessing memory
+        while self.state.step_cycles < c {
+            self.state.tick(4);
+        }
+
+        if !self.state.mem.locked {
+            self.state.check_interrupts();
+        }
+
+        let cpu_c = self.state.step_cycles;
+        let c = if self.state.mem.double_speed {
+            cpu_c / 2
+        } else {
+            cpu_c
+        };
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.tick(cpu_c);
+        }
+        self.count_frames(c);
+
+        match self.state.mem.take_fault() {
+            Some(e) => Err(e),
+            None => Ok(c),
+        }
+    }
+
+    /// Runs until the display enters the next VBlank, or for the time a
+    /// frame takes with the LCD off, see `frames`. Returns the number of
+    /// cycles elapsed, or the first error raised, in which case the frame is
+    /// left unfinished and the next call completes it.
+    pub fn run_frame(&mut self) -> Result<u64, EmuError> {
+        let frame = self.frames;
+        let mut cycles = 0;
+
+        while self.frames == frame {
+            cycles += self.step_instruction()?;
+        }
+
+        Ok(cycles)
+    }
+
+    /// Sets the joypad buttons state, one bit per button, 0 meaning pressed
+    /// (Start, Select, B, A for `action` and Down, Up, Left, Right for
+    /// `direction`).
+    pub fn set_buttons(&mut self, action: u8, direction: u8) {
+        self.state.mem.set_joypad(action, direction);
+    }
+
+    /// Plugs a peer in the link port, replacing the previous one
+    pub fn connect_serial(&mut self, device: Box<dyn SerialDevice>) {
+        self.state.mem.serial.device = device;
+    }
+
+    /// Drains the bytes sent through the link port since the last call, e.g.
+    /// the results printed by test ROMs
+    pub fn serial_output(&mut self) -> Vec<u8> {
+        self.state.mem.serial.take_output()
+    }
+
+    /// Drains the events (speed switches...) that happened
+    /// since the last call
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.state.events)
+    }
+
+    pub fn framebuffer(&self) -> &[u32; 160 * 144] {
+        self.state.mem.display.framebuffer()
+    }
+
+    /// The screen as it is displayed, to save it with `Image::save_png`
+    pub fn screenshot(&self) -> Image {
+        Image::new(160, 144, self.framebuffer().to_vec())
+    }
+
+    /// Drains the audio samples (mono, `audio::SAMPLE_RATE` Hz) produced since
+    /// the last call.
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.state.mem.audio.take_samples()
+    }
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{Cartridge, Header};
+
+    fn emulator(title: &[u8]) -> Emulator {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x14d] = Header::compute_header_checksum(&rom);
+
+        let mut emulator = Emulator::new();
+        emulator
+            .state
+            .mem
+            .load_cartridge(Cartridge::from_bytes(rom).unwrap())
+            .unwrap();
+        emulator
+    }
+
+    fn run_frames(emulator: &mut Emulator, frames: usize) {
+        for _ in 0..frames {
+            emulator.run_frame().unwrap();
+        }
+    }
+
+    #[test]
+    fn frames() {
+        let mut emulator = Emulator::new();
+        let state = &mut emulator.state;
+
+        // JR to itself in WRAM, with the LCD off
+        state.mem.w(0xc000, 0x18);
+        state.mem.w(0xc001, 0xfe);
+        state.cpu.pc = 0xc000;
+        state.mem.display.lcdc = 0;
+
+        let cycles = emulator.run_frame().unwrap();
+        assert!((CYCLES_PER_FRAME..CYCLES_PER_FRAME + 16).contains(&cycles));
+        assert_eq!(emulator.frames(), 1);
+        assert_eq!(emulator.state.mem.display.frames, 0);
+
+        // Counted by the display once the LCD is on
+        emulator.state.mem.display.lcdc = 0x80;
+        run_frames(&mut emulator, 3);
+        assert_eq!(emulator.frames(), 4);
+        assert_eq!(emulator.state.mem.display.frames, 3);
+    }
+
+    #[test]
+    fn save_state_round_trip() {
+        let mut emulator = emulator(b"ROUND TRIP");
+        run_frames(&mut emulator, 10);
+        let state = emulator.save_state();
+        let pc = emulator.state.cpu.pc;
+
+        run_frames(&mut emulator, 10);
+        assert_ne!(emulator.save_state(), state);
+
+        emulator.load_state(&state).unwrap();
+        assert_eq!(emulator.state.cpu.pc, pc);
+        assert_eq!(emulator.save_state(), state);
+    }
+
+    #[test]
+    fn wrong_rom() {
+        let state = emulator(b"GAME A").save_state();
+        let mut emulator = emulator(b"GAME B");
+        let before = emulator.save_state();
+
+        match emulator.load_state(&state) {
+            Err(SaveStateError::WrongRom { title, .. }) => assert_eq!(title, "GAME A"),
+            result => panic!("expected WrongRom, got {:?}", result),
+        }
+        assert_eq!(emulator.save_state(), before);
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut emulator = emulator(b"BAD MAGIC");
+        let before = emulator.save_state();
+
+        let mut state = before.clone();
+        state[0] ^= 0xff;
+
+        assert!(matches!(
+            emulator.load_state(&state),
+            Err(SaveStateError::NotASaveState)
+        ));
+        assert!(matches!(
+            emulator.load_state(b"GB"),
+            Err(SaveStateError::NotASaveState)
+        ));
+        assert_eq!(emulator.save_state(), before);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut emulator = emulator(b"TRUNCATED");
+        let state = emulator.save_state();
+        run_frames(&mut emulator, 10);
+        let before = emulator.save_state();
+
+        // Cut in the middle of the machine state, which must be left as it was
+        for len in [state.len() / 2, state.len() - 1] {
+            assert!(matches!(
+                emulator.load_state(&state[..len]),
+                Err(SaveStateError::Truncated)
+            ));
+            assert_eq!(emulator.save_state(), before);
+        }
+    }
+}
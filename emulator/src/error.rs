@@ -0,0 +1,51 @@
+use crate::cartridge::CartridgeError;
+use std::fmt;
+
+/// Something went wrong in the emulated machine or around it, returned by
+/// `Emulator::step_instruction` and `Emulator::run_frame`.
+///
+/// The instruction that raised it has still been fully executed, the
+/// emulation can go on by calling them again. `Quit` is the exception, it's
+/// returned before executing anything.
+///
+/// Memory accesses are deliberately not an error: nothing faults on the DMG,
+/// writes to read-only or unmapped memory are ignored and reads of it return a
+/// fixed value. Watchpoints catch them when debugging.
+#[derive(Debug)]
+pub enum EmuError {
+    // The CPU locked up, only a reset gets it back
+    IllegalOpcode { opcode: u8, addr: u16, bank: usize },
+    Cartridge(CartridgeError),
+    Io(std::io::Error),
+    // The user asked to quit from the debugger prompt
+    Quit,
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmuError::IllegalOpcode { opcode, addr, bank } => write!(
+                f,
+                "illegal opcode 0x{:02x} at {:02x}:{:04x}, the CPU is locked up",
+                opcode, bank, addr
+            ),
+            EmuError::Cartridge(e) => write!(f, "{}", e),
+            EmuError::Io(e) => write!(f, "{}", e),
+            EmuError::Quit => write!(f, "quit from the debugger"),
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
+
+impl From<CartridgeError> for EmuError {
+    fn from(e: CartridgeError) -> Self {
+        EmuError::Cartridge(e)
+    }
+}
+
+impl From<std::io::Error> for EmuError {
+    fn from(e: std::io::Error) -> Self {
+        EmuError::Io(e)
+    }
+}
@@ -0,0 +1,51 @@
+use crate::audio::{MutableWave, SAMPLE_RATE};
+use rodio::{OutputStream, Sink, Source};
+use std::time::Duration;
+
+struct WaveSource(MutableWave);
+
+impl Iterator for WaveSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.0.next()
+    }
+}
+
+impl Source for WaveSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+pub struct AudioOutput {
+    _stream: OutputStream,
+    _sink: Sink,
+}
+
+impl AudioOutput {
+    pub fn new(wave: MutableWave) -> Self {
+        let (stream, stream_handle) = OutputStream::try_default().unwrap();
+
+        let sink = Sink::try_new(&stream_handle).unwrap();
+
+        sink.append(WaveSource(wave));
+
+        Self {
+            _stream: stream,
+            _sink: sink,
+        }
+    }
+}
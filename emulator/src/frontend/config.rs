@@ -0,0 +1,589 @@
+use gilrs::{Axis, Button};
+use minifb::Key;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+
+// Config file looked up in the current directory when `--config` isn't given
+pub const DEFAULT_CONFIG_FILE: &str = "emulator.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    // Gameboy buttons
+    A,
+    B,
+    Select,
+    Start,
+    Right,
+    Left,
+    Up,
+    Down,
+
+    // Emulator hotkeys
+    Pause,
+    FastForward,
+    Rewind,
+    SaveState,
+    LoadState,
+    NextSlot,
+    PreviousSlot,
+    Screenshot,
+    Debug,
+    Debugger,
+}
+
+const ACTIONS: [(&str, Action); 18] = [
+    ("a", Action::A),
+    ("b", Action::B),
+    ("select", Action::Select),
+    ("start", Action::Start),
+    ("right", Action::Right),
+    ("left", Action::Left),
+    ("up", Action::Up),
+    ("down", Action::Down),
+    ("pause", Action::Pause),
+    ("fast_forward", Action::FastForward),
+    ("rewind", Action::Rewind),
+    ("save_state", Action::SaveState),
+    ("load_state", Action::LoadState),
+    ("next_slot", Action::NextSlot),
+    ("previous_slot", Action::PreviousSlot),
+    ("screenshot", Action::Screenshot),
+    ("debug", Action::Debug),
+    ("debugger", Action::Debugger),
+];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, action)| *action)
+    }
+}
+
+/// A keyboard key, gamepad button or gamepad axis direction, written
+/// "key:<minifb Key>", "button:<gilrs Button>" or "axis:<gilrs Axis>+/-" in
+/// the config.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Input {
+    Key(Key),
+    Button(Button),
+    // The bool is true for the positive direction of the axis
+    Axis(Axis, bool),
+}
+
+const KEYS: [(&str, Key); 106] = [
+    ("Key0", Key::Key0),
+    ("Key1", Key::Key1),
+    ("Key2", Key::Key2),
+    ("Key3", Key::Key3),
+    ("Key4", Key::Key4),
+    ("Key5", Key::Key5),
+    ("Key6", Key::Key6),
+    ("Key7", Key::Key7),
+    ("Key8", Key::Key8),
+    ("Key9", Key::Key9),
+    ("A", Key::A),
+    ("B", Key::B),
+    ("C", Key::C),
+    ("D", Key::D),
+    ("E", Key::E),
+    ("F", Key::F),
+    ("G", Key::G),
+    ("H", Key::H),
+    ("I", Key::I),
+    ("J", Key::J),
+    ("K", Key::K),
+    ("L", Key::L),
+    ("M", Key::M),
+    ("N", Key::N),
+    ("O", Key::O),
+    ("P", Key::P),
+    ("Q", Key::Q),
+    ("R", Key::R),
+    ("S", Key::S),
+    ("T", Key::T),
+    ("U", Key::U),
+    ("V", Key::V),
+    ("W", Key::W),
+    ("X", Key::X),
+    ("Y", Key::Y),
+    ("Z", Key::Z),
+    ("F1", Key::F1),
+    ("F2", Key::F2),
+    ("F3", Key::F3),
+    ("F4", Key::F4),
+    ("F5", Key::F5),
+    ("F6", Key::F6),
+    ("F7", Key::F7),
+    ("F8", Key::F8),
+    ("F9", Key::F9),
+    ("F10", Key::F10),
+    ("F11", Key::F11),
+    ("F12", Key::F12),
+    ("F13", Key::F13),
+    ("F14", Key::F14),
+    ("F15", Key::F15),
+    ("Down", Key::Down),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("Up", Key::Up),
+    ("Apostrophe", Key::Apostrophe),
+    ("Backquote", Key::Backquote),
+    ("Backslash", Key::Backslash),
+    ("Comma", Key::Comma),
+    ("Equal", Key::Equal),
+    ("LeftBracket", Key::LeftBracket),
+    ("Minus", Key::Minus),
+    ("Period", Key::Period),
+    ("RightBracket", Key::RightBracket),
+    ("Semicolon", Key::Semicolon),
+    ("Slash", Key::Slash),
+    ("Backspace", Key::Backspace),
+    ("Delete", Key::Delete),
+    ("End", Key::End),
+    ("Enter", Key::Enter),
+    ("Escape", Key::Escape),
+    ("Home", Key::Home),
+    ("Insert", Key::Insert),
+    ("Menu", Key::Menu),
+    ("PageDown", Key::PageDown),
+    ("PageUp", Key::PageUp),
+    ("Pause", Key::Pause),
+    ("Space", Key::Space),
+    ("Tab", Key::Tab),
+    ("NumLock", Key::NumLock),
+    ("CapsLock", Key::CapsLock),
+    ("ScrollLock", Key::ScrollLock),
+    ("LeftShift", Key::LeftShift),
+    ("RightShift", Key::RightShift),
+    ("LeftCtrl", Key::LeftCtrl),
+    ("RightCtrl", Key::RightCtrl),
+    ("NumPad0", Key::NumPad0),
+    ("NumPad1", Key::NumPad1),
+    ("NumPad2", Key::NumPad2),
+    ("NumPad3", Key::NumPad3),
+    ("NumPad4", Key::NumPad4),
+    ("NumPad5", Key::NumPad5),
+    ("NumPad6", Key::NumPad6),
+    ("NumPad7", Key::NumPad7),
+    ("NumPad8", Key::NumPad8),
+    ("NumPad9", Key::NumPad9),
+    ("NumPadDot", Key::NumPadDot),
+    ("NumPadSlash", Key::NumPadSlash),
+    ("NumPadAsterisk", Key::NumPadAsterisk),
+    ("NumPadMinus", Key::NumPadMinus),
+    ("NumPadPlus", Key::NumPadPlus),
+    ("NumPadEnter", Key::NumPadEnter),
+    ("LeftAlt", Key::LeftAlt),
+    ("RightAlt", Key::RightAlt),
+    ("LeftSuper", Key::LeftSuper),
+    ("RightSuper", Key::RightSuper),
+];
+
+const BUTTONS: [(&str, Button); 19] = [
+    ("South", Button::South),
+    ("East", Button::East),
+    ("North", Button::North),
+    ("West", Button::West),
+    ("C", Button::C),
+    ("Z", Button::Z),
+    ("LeftTrigger", Button::LeftTrigger),
+    ("LeftTrigger2", Button::LeftTrigger2),
+    ("RightTrigger", Button::RightTrigger),
+    ("RightTrigger2", Button::RightTrigger2),
+    ("Select", Button::Select),
+    ("Start", Button::Start),
+    ("Mode", Button::Mode),
+    ("LeftThumb", Button::LeftThumb),
+    ("RightThumb", Button::RightThumb),
+    ("DPadUp", Button::DPadUp),
+    ("DPadDown", Button::DPadDown),
+    ("DPadLeft", Button::DPadLeft),
+    ("DPadRight", Button::DPadRight),
+];
+
+const AXES: [(&str, Axis); 8] = [
+    ("LeftStickX", Axis::LeftStickX),
+    ("LeftStickY", Axis::LeftStickY),
+    ("LeftZ", Axis::LeftZ),
+    ("RightStickX", Axis::RightStickX),
+    ("RightStickY", Axis::RightStickY),
+    ("RightZ", Axis::RightZ),
+    ("DPadX", Axis::DPadX),
+    ("DPadY", Axis::DPadY),
+];
+
+fn lookup<T: Copy>(table: &[(&str, T)], name: &str) -> Option<T> {
+    table.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+}
+
+impl Input {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (kind, name) = s.split_once(':')?;
+
+        match kind {
+            "key" => lookup(&KEYS, name).map(Input::Key),
+            "button" => lookup(&BUTTONS, name).map(Input::Button),
+            "axis" => {
+                let (name, positive) = if let Some(name) = name.strip_suffix('+') {
+                    (name, true)
+                } else {
+                    (name.strip_suffix('-')?, false)
+                };
+
+                lookup(&AXES, name).map(|axis| Input::Axis(axis, positive))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    UnknownAction(String),
+    UnknownInput(String),
+    // A `--bind` argument that isn't of the form action=input
+    InvalidBinding(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::Toml(e) => write!(f, "{}", e),
+            ConfigError::UnknownAction(name) => write!(
+                f,
+                "unknown action {:?}, expected one of: {}",
+                name,
+                ACTIONS.map(|(n, _)| n).join(", ")
+            ),
+            ConfigError::UnknownInput(name) => write!(
+                f,
+                "unknown input {:?}, expected \"key:<Key>\", \"button:<Button>\" or \"axis:<Axis>+\"/\"axis:<Axis>-\"",
+                name
+            ),
+            ConfigError::InvalidBinding(binding) => {
+                write!(f, "invalid binding {:?}, expected <action>=<input>", binding)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Toml(e)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    axis_threshold: Option<f32>,
+    fast_forward_speed: Option<f32>,
+    rewind_memory_mib: Option<usize>,
+    rewind_interval: Option<u64>,
+    #[serde(default)]
+    bindings: HashMap<String, Vec<String>>,
+}
+
+/// Input bindings and hotkey settings of the frontend
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bindings: HashMap<Action, Vec<Input>>,
+
+    // How far an axis has to be pushed for it to count as pressed (0.0-1.0)
+    pub axis_threshold: f32,
+
+    // Speed multiplier while the fast_forward hotkey is held
+    pub fast_forward_speed: f32,
+
+    // Memory budget of the rewind snapshots in MiB, 0 disables rewinding
+    pub rewind_memory_mib: usize,
+
+    // Number of frames between two rewind snapshots
+    pub rewind_interval: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let bindings = [
+            (
+                Action::A,
+                vec![Input::Key(Key::X), Input::Button(Button::East)],
+            ),
+            (
+                Action::B,
+                vec![Input::Key(Key::Z), Input::Button(Button::South)],
+            ),
+            (
+                Action::Select,
+                vec![Input::Key(Key::Backspace), Input::Button(Button::Select)],
+            ),
+            (
+                Action::Start,
+                vec![Input::Key(Key::Enter), Input::Button(Button::Start)],
+            ),
+            (
+                Action::Right,
+                vec![
+                    Input::Key(Key::Right),
+                    Input::Button(Button::DPadRight),
+                    Input::Axis(Axis::LeftStickX, true),
+                ],
+            ),
+            (
+                Action::Left,
+                vec![
+                    Input::Key(Key::Left),
+                    Input::Button(Button::DPadLeft),
+                    Input::Axis(Axis::LeftStickX, false),
+                ],
+            ),
+            (
+                Action::Up,
+                vec![
+                    Input::Key(Key::Up),
+                    Input::Button(Button::DPadUp),
+                    Input::Axis(Axis::LeftStickY, true),
+                ],
+            ),
+            (
+                Action::Down,
+                vec![
+                    Input::Key(Key::Down),
+                    Input::Button(Button::DPadDown),
+                    Input::Axis(Axis::LeftStickY, false),
+                ],
+            ),
+            (Action::Pause, vec![Input::Key(Key::P)]),
+            (
+                Action::FastForward,
+                vec![Input::Key(Key::Space), Input::Button(Button::RightTrigger)],
+            ),
+            (
+                Action::Rewind,
+                vec![Input::Key(Key::R), Input::Button(Button::LeftTrigger)],
+            ),
+            (Action::SaveState, vec![Input::Key(Key::F5)]),
+            (Action::LoadState, vec![Input::Key(Key::F8)]),
+            (Action::NextSlot, vec![Input::Key(Key::F7)]),
+            (Action::PreviousSlot, vec![Input::Key(Key::F6)]),
+            (Action::Screenshot, vec![Input::Key(Key::F12)]),
+            (Action::Debug, vec![Input::Button(Button::West)]),
+            (Action::Debugger, vec![Input::Key(Key::F9)]),
+        ];
+
+        Self {
+            bindings: bindings.into_iter().collect(),
+            axis_threshold: 0.5,
+            fast_forward_speed: 4.0,
+            rewind_memory_mib: 64,
+            rewind_interval: 4,
+        }
+    }
+}
+
+impl Config {
+    /// Loads a TOML config file on top of the default config. Each action
+    /// listed in its `[bindings]` table replaces the default bindings of that
+    /// action:
+    ///
+    /// ```toml
+    /// fast_forward_speed = 8.0
+    ///
+    /// [bindings]
+    /// a = ["key:S", "button:East"]
+    /// up = ["key:W", "axis:LeftStickY+"]
+    /// ```
+    pub fn load(file: &str) -> Result<Self, ConfigError> {
+        Self::parse(&std::fs::read_to_string(file)?)
+    }
+
+    /// Same as `load` from the content of the file
+    pub fn parse(content: &str) -> Result<Self, ConfigError> {
+        let config_file: ConfigFile = toml::from_str(content)?;
+
+        let mut config = Self::default();
+
+        if let Some(axis_threshold) = config_file.axis_threshold {
+            config.axis_threshold = axis_threshold;
+        }
+        if let Some(fast_forward_speed) = config_file.fast_forward_speed {
+            config.fast_forward_speed = fast_forward_speed;
+        }
+        if let Some(rewind_memory_mib) = config_file.rewind_memory_mib {
+            config.rewind_memory_mib = rewind_memory_mib;
+        }
+        if let Some(rewind_interval) = config_file.rewind_interval {
+            config.rewind_interval = rewind_interval;
+        }
+
+        for (name, inputs) in config_file.bindings {
+            let action = Action::from_name(&name).ok_or(ConfigError::UnknownAction(name))?;
+
+            let inputs = inputs
+                .iter()
+                .map(|input| Input::parse(input).ok_or(ConfigError::UnknownInput(input.clone())))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            config.bindings.insert(action, inputs);
+        }
+
+        Ok(config)
+    }
+
+    /// Applies the `--bind <action>=<input>[,<input>...]` command line
+    /// overrides, replacing the bindings of each given action.
+    pub fn apply_overrides(&mut self, overrides: &[String]) -> Result<(), ConfigError> {
+        for binding in overrides {
+            let (name, inputs) = binding
+                .split_once('=')
+                .ok_or(ConfigError::InvalidBinding(binding.clone()))?;
+
+            let action =
+                Action::from_name(name).ok_or(ConfigError::UnknownAction(name.to_string()))?;
+
+            let inputs = inputs
+                .split(',')
+                .filter(|input| !input.is_empty())
+                .map(|input| {
+                    Input::parse(input).ok_or(ConfigError::UnknownInput(input.to_string()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            self.bindings.insert(action, inputs);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_inputs() {
+        assert_eq!(Input::parse("key:S"), Some(Input::Key(Key::S)));
+        assert_eq!(Input::parse("key:NumPad0"), Some(Input::Key(Key::NumPad0)));
+        assert_eq!(
+            Input::parse("button:East"),
+            Some(Input::Button(Button::East))
+        );
+        assert_eq!(
+            Input::parse("axis:LeftStickY+"),
+            Some(Input::Axis(Axis::LeftStickY, true))
+        );
+        assert_eq!(
+            Input::parse("axis:DPadX-"),
+            Some(Input::Axis(Axis::DPadX, false))
+        );
+
+        for input in [
+            "S",
+            "key:s",
+            "key:",
+            "button:Key0",
+            "axis:LeftStickY",
+            "axis:Nothing+",
+            "mouse:Left",
+        ] {
+            assert_eq!(Input::parse(input), None, "{}", input);
+        }
+    }
+
+    #[test]
+    fn parse_config() {
+        let config = Config::parse(
+            r#"
+            fast_forward_speed = 8.0
+            rewind_interval = 2
+
+            [bindings]
+            a = ["key:S", "axis:RightZ+"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.fast_forward_speed, 8.0);
+        assert_eq!(config.rewind_interval, 2);
+        assert_eq!(config.axis_threshold, 0.5);
+        assert_eq!(
+            config.bindings[&Action::A],
+            [Input::Key(Key::S), Input::Axis(Axis::RightZ, true)]
+        );
+
+        // Everything else keeps the defaults
+        let default = Config::default();
+        assert_eq!(config.bindings.len(), default.bindings.len());
+        for (action, inputs) in &default.bindings {
+            if *action != Action::A {
+                assert_eq!(&config.bindings[action], inputs);
+            }
+        }
+    }
+
+    #[test]
+    fn config_errors() {
+        assert!(matches!(
+            Config::parse("speed = 2.0"),
+            Err(ConfigError::Toml(_))
+        ));
+        assert!(matches!(
+            Config::parse("[bindings]\njump = [\"key:Space\"]"),
+            Err(ConfigError::UnknownAction(name)) if name == "jump"
+        ));
+        assert!(matches!(
+            Config::parse("[bindings]\na = [\"key:Nothing\"]"),
+            Err(ConfigError::UnknownInput(input)) if input == "key:Nothing"
+        ));
+    }
+
+    #[test]
+    fn overrides() {
+        let mut config = Config::default();
+        config
+            .apply_overrides(&["a=key:K".to_string(), "b=key:J,button:West".to_string()])
+            .unwrap();
+
+        assert_eq!(config.bindings[&Action::A], [Input::Key(Key::K)]);
+        assert_eq!(
+            config.bindings[&Action::B],
+            [Input::Key(Key::J), Input::Button(Button::West)]
+        );
+        assert_eq!(
+            config.bindings[&Action::Start],
+            Config::default().bindings[&Action::Start]
+        );
+    }
+
+    #[test]
+    fn invalid_overrides() {
+        let mut config = Config::default();
+
+        assert!(matches!(
+            config.apply_overrides(&["a".to_string()]),
+            Err(ConfigError::InvalidBinding(binding)) if binding == "a"
+        ));
+        assert!(matches!(
+            config.apply_overrides(&["jump=key:Space".to_string()]),
+            Err(ConfigError::UnknownAction(_))
+        ));
+        assert!(matches!(
+            config.apply_overrides(&["a=key:Space,key:".to_string()]),
+            Err(ConfigError::UnknownInput(_))
+        ));
+    }
+}
@@ -0,0 +1,45 @@
+use gilrs::{Axis, Button, GamepadId, Gilrs};
+
+pub struct Gamepad {
+    gilrs: Gilrs,
+    gamepad_id: Option<GamepadId>,
+}
+
+impl Gamepad {
+    pub fn new() -> Self {
+        let gilrs = Gilrs::new().unwrap();
+
+        let gamepad_id = if let Some((gamepad_id, _gamepad)) = gilrs.gamepads().next() {
+            println!("Found Gamepad id: {:?}", gamepad_id);
+            Some(gamepad_id)
+        } else {
+            println!("No gamepad found");
+            None
+        };
+
+        Self { gilrs, gamepad_id }
+    }
+
+    pub fn update_events(&mut self) {
+        while self.gilrs.next_event().is_some() {}
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.gamepad_id
+            .and_then(|gamepad_id| self.gilrs.connected_gamepad(gamepad_id))
+            .is_some_and(|gamepad| gamepad.is_pressed(button))
+    }
+
+    pub fn axis_value(&self, axis: Axis) -> f32 {
+        self.gamepad_id
+            .and_then(|gamepad_id| self.gilrs.connected_gamepad(gamepad_id))
+            .and_then(|gamepad| gamepad.axis_data(axis).map(|data| data.value()))
+            .unwrap_or(0.)
+    }
+}
+
+impl Default for Gamepad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,80 @@
+use crate::frontend::config::{Action, Config, Input};
+use crate::frontend::gamepad::Gamepad;
+use crate::frontend::window::Window;
+
+// Bits of the joypad register nibbles, in the order of the register
+const ACTION_BUTTONS: [Action; 4] = [Action::A, Action::B, Action::Select, Action::Start];
+const DIRECTION_BUTTONS: [Action; 4] = [Action::Right, Action::Left, Action::Up, Action::Down];
+
+/// State of the actions bound in the config, from both the keyboard and the
+/// gamepad.
+pub struct Controls {
+    pub config: Config,
+    // Bitmasks indexed by `Action as u32`
+    pressed: u32,
+    last_pressed: u32,
+}
+
+impl Controls {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            pressed: 0,
+            last_pressed: 0,
+        }
+    }
+
+    fn is_input_active(&self, input: &Input, window: &Window, gamepad: &Gamepad) -> bool {
+        match *input {
+            Input::Key(key) => window.is_key_down(key),
+            Input::Button(button) => gamepad.is_pressed(button),
+            Input::Axis(axis, true) => gamepad.axis_value(axis) > self.config.axis_threshold,
+            Input::Axis(axis, false) => gamepad.axis_value(axis) < -self.config.axis_threshold,
+        }
+    }
+
+    pub fn update(&mut self, window: &Window, gamepad: &Gamepad) {
+        let pressed = self
+            .config
+            .bindings
+            .iter()
+            .filter(|(_, inputs)| {
+                inputs
+                    .iter()
+                    .any(|input| self.is_input_active(input, window, gamepad))
+            })
+            .fold(0, |pressed, (action, _)| pressed | (1 << *action as u32));
+
+        self.last_pressed = std::mem::replace(&mut self.pressed, pressed);
+    }
+
+    pub fn is_pressed(&self, action: Action) -> bool {
+        self.pressed & (1 << action as u32) != 0
+    }
+
+    /// True only on the update where the action went from released to pressed,
+    /// for hotkeys that shouldn't repeat while held.
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.is_pressed(action) && self.last_pressed & (1 << action as u32) == 0
+    }
+
+    fn joypad_reg(&self, buttons: &[Action; 4]) -> u8 {
+        let mut res = 0xf;
+
+        for (i, button) in buttons.iter().enumerate() {
+            if self.is_pressed(*button) {
+                res &= !(1 << i);
+            }
+        }
+
+        res
+    }
+
+    pub fn get_action_reg(&self) -> u8 {
+        self.joypad_reg(&ACTION_BUTTONS)
+    }
+
+    pub fn get_direction_reg(&self) -> u8 {
+        self.joypad_reg(&DIRECTION_BUTTONS)
+    }
+}
@@ -0,0 +1,8 @@
+//! Desktop frontend: a minifb window (also used for keyboard input), rodio audio
+//! output and gilrs gamepads on top of the core `Emulator`.
+
+pub mod audio;
+pub mod config;
+pub mod gamepad;
+pub mod input;
+pub mod window;
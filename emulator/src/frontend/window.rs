@@ -0,0 +1,36 @@
+use minifb::{Key, WindowOptions};
+
+pub struct Window {
+    window: minifb::Window,
+}
+
+impl Window {
+    pub fn new() -> Self {
+        Self {
+            window: minifb::Window::new(
+                "Gameboy Emulator",
+                /*512, 461*/ 1200,
+                1080,
+                WindowOptions::default(),
+            )
+            .unwrap(),
+        }
+    }
+
+    pub fn update(&mut self, framebuffer: &[u32; 160 * 144]) {
+        self.window
+            .update_with_buffer(framebuffer, 160, 144)
+            .unwrap();
+    }
+
+    // The key state is refreshed by `update`
+    pub fn is_key_down(&self, key: Key) -> bool {
+        self.window.is_key_down(key)
+    }
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        Self::new()
+    }
+}
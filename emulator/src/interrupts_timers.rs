@@ -1,42 +1,82 @@
 use crate::display::DisplayInterrupt;
-use crate::opcodes;
-use crate::state::{GBState, MemError};
-
-const TIMA_TIMER_SPEEDS: [u64; 4] = [1024, 16, 64, 256];
+use crate::state::GBState;
 
 impl GBState {
-    pub fn check_interrupts(&mut self) -> Result<(), MemError> {
-        if self.mem.ime {
-            let interrupts = self.mem.io[0x0f] & self.mem.interrupts_register & 0b11111;
-            for i in 0..5 {
-                if interrupts & (1 << i) != 0 {
-                    opcodes::push(self, self.cpu.pc)?;
-
-                    self.mem.ime = false;
-                    self.cpu.pc = 0x40 + (i << 3);
-                    self.mem.halt = false;
-
-                    self.mem.io[0x0f] &= !(1 << i);
-                    break;
-                }
-            }
+    /// Interrupts both requested and enabled
+    pub fn pending_interrupts(&self) -> u8 {
+        self.mem.io[0x0f] & self.mem.interrupts_register & 0b11111
+    }
+
+    /// Dispatches the highest priority pending interrupt when IME is set,
+    /// returns the cycles it took
+    pub fn check_interrupts(&mut self) -> u64 {
+        let interrupts = self.pending_interrupts();
+
+        // HALT is left on any pending interrupt, even when IME is off and
+        // the interrupt isn't handled
+        if interrupts != 0 {
+            self.mem.halt = false;
+        }
+
+        if !self.mem.ime || interrupts == 0 {
+            return 0;
+        }
+
+        self.mem.ime = false;
+
+        // EI right before a HALT hitting the bug: the handler returns to the
+        // HALT, which is executed again
+        if self.mem.halt_bug {
+            self.mem.halt_bug = false;
+            self.cpu.pc -= 1;
         }
-        Ok(())
+
+        // Two wait cycles and one to decrement SP, then PC is pushed
+        self.tick(12);
+        self.cpu.sp -= 1;
+        self.write(self.cpu.sp, (self.cpu.pc >> 8) as u8);
+
+        // The interrupt is only picked now: pushing the high byte to IE (with
+        // SP at 0x0000) can cancel it, PC is then set to 0x0000
+        let interrupts = self.pending_interrupts();
+
+        self.cpu.sp -= 1;
+        self.write(self.cpu.sp, (self.cpu.pc & 0xff) as u8);
+
+        if interrupts == 0 {
+            self.cpu.pc = 0;
+        } else {
+            let i = interrupts.trailing_zeros() as u16;
+
+            self.cpu.pc = 0x40 + (i << 3);
+            self.mem.io[0x0f] &= !(1 << i);
+        }
+
+        20
     }
 
-    pub fn tima_timer(&mut self, c: u64) {
-        if self.mem.timer_enabled
-            && self.tima_cycles >= TIMA_TIMER_SPEEDS[self.mem.timer_speed as usize]
-        {
-            if self.mem.tima == 0xff {
-                self.mem.io[0x0f] |= 0b100;
-                self.mem.tima = self.mem.tma;
-            } else {
-                self.mem.tima += 1;
-            }
-            self.tima_cycles %= TIMA_TIMER_SPEEDS[self.mem.timer_speed as usize];
+    /// Advances everything but the CPU by `c` CPU cycles. In double speed
+    /// the timers follow the CPU, the display and the audio keep their speed.
+    pub fn tick(&mut self, c: u64) {
+        if self.mem.timer.tick(c) {
+            self.mem.io[0x0f] |= 0b100;
         }
-        self.tima_cycles += c;
+        if self.mem.serial.tick(c) {
+            self.mem.io[0x0f] |= 0b1000;
+        }
+        self.tick_clock_stopped(c);
+    }
+
+    /// Same as `tick` while the CPU clock is stopped by a speed switch: DIV,
+    /// the timer and the serial port, which are driven by it, don't advance.
+    pub fn tick_clock_stopped(&mut self, c: u64) {
+        self.step_cycles += c;
+
+        let display_c = if self.mem.double_speed { c / 2 } else { c };
+
+        self.update_display_interrupts(display_c);
+        self.mem.audio.tick(display_c);
+        self.mem.tick_cartridge(display_c);
     }
 
     pub fn update_display_interrupts(&mut self, c: u64) {
@@ -55,13 +95,118 @@ impl GBState {
             _ => {}
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// IME set, with the LCD off so that the display doesn't request
+    /// anything during the dispatch
+    fn state(pc: u16, sp: u16) -> GBState {
+        let mut state = GBState::new();
+        state.mem.display.lcdc = 0;
+        state.mem.ime = true;
+        state.cpu.pc = pc;
+        state.cpu.sp = sp;
+
+        state
+    }
+
+    fn pushed(state: &GBState) -> u16 {
+        u16::from_le_bytes([state.mem.r(state.cpu.sp), state.mem.r(state.cpu.sp + 1)])
+    }
+
+    #[test]
+    fn dispatch() {
+        let mut state = state(0xc123, 0xdff0);
+        state.mem.interrupts_register = 0b00100;
+        state.mem.io[0x0f] = 0b00100;
+
+        assert_eq!(state.check_interrupts(), 20);
+        assert_eq!(state.step_cycles, 20);
+        assert_eq!(state.cpu.pc, 0x50);
+        assert_eq!(state.cpu.sp, 0xdfee);
+        assert_eq!(pushed(&state), 0xc123);
+        assert_eq!(state.mem.io[0x0f], 0);
+        assert!(!state.mem.ime);
+
+        // Nothing more once IME is off
+        state.mem.io[0x0f] = 0b00100;
+        assert_eq!(state.check_interrupts(), 0);
+        assert_eq!(state.cpu.pc, 0x50);
+    }
 
-    pub fn div_timer(&mut self, c: u64) {
-        if self.div_cycles >= 256 {
-            self.mem.div += 1;
+    #[test]
+    fn not_dispatched() {
+        // Requested but not enabled
+        let mut state = state(0xc123, 0xdff0);
+        state.mem.interrupts_register = 0b01111;
+        state.mem.io[0x0f] = 0b10000;
 
-            self.div_cycles = 0;
+        assert_eq!(state.check_interrupts(), 0);
+        assert_eq!(state.step_cycles, 0);
+        assert_eq!((state.cpu.pc, state.cpu.sp), (0xc123, 0xdff0));
+        assert!(state.mem.ime);
+
+        // Pending but IME is off
+        state.mem.interrupts_register = 0b11111;
+        state.mem.ime = false;
+        assert_eq!(state.check_interrupts(), 0);
+        assert_eq!(state.cpu.pc, 0xc123);
+        assert_eq!(state.mem.io[0x0f], 0b10000);
+    }
+
+    #[test]
+    fn priority() {
+        let mut state = state(0xc123, 0xdff0);
+        state.mem.interrupts_register = 0b11111;
+        state.mem.io[0x0f] = 0b11111;
+
+        // VBlank, STAT, timer, serial then joypad, each one clearing its bit
+        for (i, vector) in [0x40, 0x48, 0x50, 0x58, 0x60].into_iter().enumerate() {
+            state.mem.ime = true;
+            assert_eq!(state.check_interrupts(), 20);
+            assert_eq!(state.cpu.pc, vector);
+            assert_eq!(state.mem.io[0x0f], 0b11111 & !((2 << i) - 1));
         }
-        self.div_cycles += c;
+
+        // Only the enabled ones are considered
+        state.mem.ime = true;
+        state.mem.interrupts_register = 0b11000;
+        state.mem.io[0x0f] = 0b10111;
+        state.check_interrupts();
+        assert_eq!(state.cpu.pc, 0x60);
+        assert_eq!(state.mem.io[0x0f], 0b00111);
+    }
+
+    #[test]
+    fn push_to_ie_cancels_the_dispatch() {
+        // The high byte of PC (0x02, STAT) replaces the joypad in IE
+        let mut state = state(0x0234, 0x0000);
+        state.mem.interrupts_register = 0b10000;
+        state.mem.io[0x0f] = 0b10000;
+
+        assert_eq!(state.check_interrupts(), 20);
+        assert_eq!(state.cpu.pc, 0x0000);
+        assert_eq!(state.cpu.sp, 0xfffe);
+        assert_eq!(state.mem.interrupts_register, 0x02);
+        assert_eq!(state.mem.r(0xfffe), 0x34);
+        assert_eq!(state.mem.io[0x0f], 0b10000);
+        assert!(!state.mem.ime);
+    }
+
+    #[test]
+    fn push_to_ie_changes_the_interrupt() {
+        // VBlank is pending, the high byte of PC (0x10) leaves only the
+        // joypad enabled
+        let mut state = state(0x1000, 0x0000);
+        state.mem.interrupts_register = 0b00001;
+        state.mem.io[0x0f] = 0b10001;
+
+        assert_eq!(state.check_interrupts(), 20);
+        assert_eq!(state.cpu.pc, 0x60);
+        assert_eq!(state.mem.interrupts_register, 0x10);
+        assert_eq!(state.mem.io[0x0f], 0b00001);
     }
 }
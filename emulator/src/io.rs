@@ -1,6 +1,18 @@
-use crate::state::{MemError, Memory};
+use crate::state::Memory;
 
 impl Memory {
+    pub fn set_joypad(&mut self, action_button_reg: u8, direction_button_reg: u8) {
+        if self.joypad_is_action
+            && (action_button_reg & (self.joypad_reg >> 4)) != (self.joypad_reg >> 4)
+            || (!self.joypad_is_action
+                && (direction_button_reg & self.joypad_reg & 0b1111) != (self.joypad_reg & 0b1111))
+        {
+            self.io[0x0f] |= 0b10000;
+        }
+
+        self.joypad_reg = direction_button_reg | (action_button_reg << 4);
+    }
+
     pub fn r_io(&self, addr: u8) -> u8 {
         if addr > 0x50 {
             println!("Reading from 0xff{:02x} not implemented yet", addr);
@@ -13,7 +25,11 @@ impl Memory {
                     (self.joypad_reg & 0xf) | 0b11100000
                 }
             }
-            0x04 => self.div,
+            0x01 => self.serial.r(addr),
+            // The clock speed bit only exists on CGB
+            0x02 if self.cgb_mode => self.serial.r(addr),
+            0x02 => self.serial.r(addr) | 0b10,
+            0x04..=0x07 => self.timer.r(addr),
             0x40 => self.display.lcdc,
             0x42 => self.display.viewport_y,
             0x43 => self.display.viewport_x,
@@ -49,6 +65,13 @@ impl Memory {
             0x49 => self.display.obj_palettes[1],
             0x4a => self.display.window_y,
             0x4b => self.display.window_x,
+            0x4d => {
+                if self.cgb_mode {
+                    0b01111110 | ((self.double_speed as u8) << 7) | self.speed_switch_armed as u8
+                } else {
+                    0xff
+                }
+            }
             0x50 => {
                 if self.boot_rom_on {
                     0xfe
@@ -63,23 +86,25 @@ impl Memory {
         }
     }
 
-    pub fn w_io(&mut self, addr: u8, value: u8) -> Result<(), MemError> {
+    pub fn w_io(&mut self, addr: u8, value: u8) {
+        if self.is_watched(0xff00 | addr as u16) {
+            let old = self.r_io(addr);
+            self.check_watchpoints(0xff00 | addr as u16, true, old, value);
+        }
+
         match addr {
             0x00 => {
                 self.joypad_is_action = !value & 0b00100000 != 0;
             }
-            0x04 => {
-                self.div = 0;
+            0x01 => {
+                self.serial.w(addr, value);
             }
-            0x05 => {
-                self.tima = value;
+            0x02 => {
+                self.serial
+                    .w(addr, if self.cgb_mode { value } else { value & !0b10 });
             }
-            0x06 => {
-                self.tma = value;
-            }
-            0x07 => {
-                self.timer_enabled = value & 0b100 != 0;
-                self.timer_speed = value & 0b11;
+            0x04..=0x07 => {
+                self.timer.w(addr, value);
             }
             0x0f => {
                 self.io[0x0f] = value;
@@ -132,11 +157,7 @@ impl Memory {
                 }
             }
             0x1a => {
-                if value & 0b10000000 != 0 {
-                    self.audio.ch3.on = true;
-                } else {
-                    self.audio.ch3.on = false;
-                }
+                self.audio.ch3.on = value & 0b10000000 != 0;
                 self.audio.ch3.update();
             }
             0x1b => {
@@ -202,7 +223,7 @@ impl Memory {
                     let addr = (value as u16) << 8;
 
                     for i in 0..0xa0 {
-                        self.w(0xfe00 | i, self.r(addr | i)?)?;
+                        self.w(0xfe00 | i, self.r(addr | i));
                     }
                 }
             }
@@ -217,6 +238,11 @@ impl Memory {
                 self.bgcram_pointer = 0b111111 & value;
                 self.bgcram_pointer_autoincrement = value & 0b10000000 != 0;
             }
+            0x4d => {
+                if self.cgb_mode {
+                    self.speed_switch_armed = value & 1 != 0;
+                }
+            }
             0x69 => {
                 self.display.cram[self.bgcram_pointer as usize] = value;
                 if self.bgcram_pointer_autoincrement {
@@ -246,12 +272,10 @@ impl Memory {
         }
         self.io[addr as usize] = value;
 
-        if addr >= 0x30 && addr <= 0x3f {
+        if (0x30..=0x3f).contains(&addr) {
             let i = (addr - 0x30) as usize;
             self.audio.ch3.wave_pattern[i * 2] = value >> 4;
             self.audio.ch3.wave_pattern[i * 2 + 1] = value & 0xf;
         }
-
-        Ok(())
     }
 }
@@ -0,0 +1,29 @@
+pub mod audio;
+pub mod cartridge;
+pub mod consts;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod emulator;
+pub mod error;
+pub mod interrupts_timers;
+pub mod io;
+pub mod link;
+pub mod mbc;
+pub mod opcodes;
+pub mod rewind;
+pub mod rtc;
+pub mod savestate;
+pub mod screenshot;
+pub mod serial;
+pub mod state;
+pub mod testrom;
+pub mod timer;
+pub mod trace;
+
+#[cfg(feature = "frontend")]
+pub mod frontend;
+
+pub use crate::emulator::Emulator;
+pub use crate::error::EmuError;
+pub use crate::state::{Event, GBState};
@@ -0,0 +1,331 @@
+use crate::serial::{Clock, SerialDevice};
+use std::collections::VecDeque;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+const MAGIC: &[u8; 6] = b"GBLINK";
+const PROTOCOL_VERSION: u8 = 1;
+
+// Neither side runs more than WINDOW cycles ahead of the last cycle count
+// announced by the other one, which happens every SYNC_INTERVAL cycles. A
+// transfer started at cycle S completes at S + 4096 > S + WINDOW, so the
+// peer always gets the request before reaching its completion. The CGB fast
+// clock transfers are shorter and can complete a little late on the peer.
+const WINDOW: u64 = 2048;
+const SYNC_INTERVAL: u64 = 1024;
+
+mod message {
+    // Cycle count of the sender
+    pub const SYNC: u8 = 0;
+    // Transfer clocked by the sender, completing at the given cycle count
+    pub const REQUEST: u8 = 1;
+    // Byte shifted out by the receiver of a request
+    pub const REPLY: u8 = 2;
+}
+
+/// Link cable to another emulator over TCP.
+///
+/// The side selecting the internal clock in SC is the clock master of a
+/// transfer: it sends its byte as it starts and the peer answers with its
+/// own SB when the transfer completes. When both sides use the internal clock
+/// each one clocks its own transfer, as on hardware. Only the peer waiting on
+/// the external clock completes its transfer, an idle one answers 0xff.
+///
+/// Both emulators count the CPU cycles since the connection and run in
+/// lockstep, so every transfer happens at the same cycle on both sides
+/// whatever the latency of the network is.
+pub struct TcpLink {
+    reader: Option<BufReader<TcpStream>>,
+    writer: Option<BufWriter<TcpStream>>,
+
+    cycles: u64,
+    next_sync: u64,
+    peer_cycles: u64,
+
+    // Transfers clocked by the peer, with the cycle they complete at
+    requests: VecDeque<(u64, u8)>,
+    reply: Option<u8>,
+}
+
+impl TcpLink {
+    /// Waits for the other emulator to connect on `port`
+    pub fn listen(port: u16) -> io::Result<Self> {
+        Self::accept(&TcpListener::bind(("0.0.0.0", port))?)
+    }
+
+    /// Same as `listen` on an already bound listener
+    pub fn accept(listener: &TcpListener) -> io::Result<Self> {
+        let (stream, _) = listener.accept()?;
+
+        Self::new(stream)
+    }
+
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+
+        let mut link = Self {
+            reader: Some(BufReader::new(stream.try_clone()?)),
+            writer: Some(BufWriter::new(stream)),
+            cycles: 0,
+            next_sync: SYNC_INTERVAL,
+            peer_cycles: 0,
+            requests: VecDeque::new(),
+            reply: None,
+        };
+
+        link.handshake()?;
+
+        Ok(link)
+    }
+
+    fn handshake(&mut self) -> io::Result<()> {
+        let writer = self.writer.as_mut().unwrap();
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[PROTOCOL_VERSION])?;
+        writer.flush()?;
+
+        let mut hello = [0; 7];
+        self.reader.as_mut().unwrap().read_exact(&mut hello)?;
+
+        if &hello[..6] != MAGIC || hello[6] != PROTOCOL_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the peer isn't a compatible emulator",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn connected(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    fn disconnect(&mut self, e: io::Error) {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            println!("Link cable disconnected by the other emulator");
+        } else {
+            println!("Link cable disconnected: {}", e);
+        }
+
+        self.reader = None;
+        self.writer = None;
+        self.requests.clear();
+    }
+
+    fn send(&mut self, data: &[u8]) {
+        if let Some(writer) = self.writer.as_mut() {
+            if let Err(e) = writer.write_all(data).and_then(|_| writer.flush()) {
+                self.disconnect(e);
+            }
+        }
+    }
+
+    fn send_sync(&mut self) {
+        let mut data = vec![message::SYNC];
+        data.extend_from_slice(&self.cycles.to_le_bytes());
+
+        self.send(&data);
+    }
+
+    /// Blocks until the next message is received and handles it
+    fn receive(&mut self) {
+        let Some(reader) = self.reader.as_mut() else {
+            return;
+        };
+
+        let mut read = || -> io::Result<()> {
+            let mut tag = [0; 1];
+            reader.read_exact(&mut tag)?;
+
+            match tag[0] {
+                message::SYNC => {
+                    let mut cycles = [0; 8];
+                    reader.read_exact(&mut cycles)?;
+                    self.peer_cycles = u64::from_le_bytes(cycles);
+                }
+                message::REQUEST => {
+                    let mut request = [0; 9];
+                    reader.read_exact(&mut request)?;
+                    self.requests.push_back((
+                        u64::from_le_bytes(request[..8].try_into().unwrap()),
+                        request[8],
+                    ));
+                }
+                message::REPLY => {
+                    let mut byte = [0; 1];
+                    reader.read_exact(&mut byte)?;
+                    self.reply = Some(byte[0]);
+                }
+                tag => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unknown message 0x{:02x}", tag),
+                    ))
+                }
+            }
+
+            Ok(())
+        };
+
+        if let Err(e) = read() {
+            self.disconnect(e);
+        }
+    }
+}
+
+impl SerialDevice for TcpLink {
+    fn start(&mut self, byte: u8, cycles: u64) {
+        let mut data = vec![message::REQUEST];
+        data.extend_from_slice(&(self.cycles + cycles).to_le_bytes());
+        data.push(byte);
+
+        self.send(&data);
+    }
+
+    fn transfer(&mut self, _byte: u8) -> u8 {
+        // The peer needs to reach this cycle to answer
+        self.send_sync();
+
+        while self.connected() && self.reply.is_none() {
+            self.receive();
+        }
+
+        self.reply.take().unwrap_or(0xff)
+    }
+
+    fn tick(&mut self, c: u64, sb: u8, clock: Clock) -> Option<u8> {
+        if !self.connected() {
+            return None;
+        }
+
+        self.cycles += c;
+
+        if self.cycles >= self.next_sync {
+            self.next_sync = self.cycles + SYNC_INTERVAL;
+            self.send_sync();
+        }
+
+        while self.connected() && self.cycles > self.peer_cycles + WINDOW {
+            self.receive();
+        }
+
+        match self.requests.front() {
+            Some(&(at, byte)) if at <= self.cycles => {
+                self.requests.pop_front();
+                self.send(&[message::REPLY, if clock == Clock::Idle { 0xff } else { sb }]);
+
+                Some(byte)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::Serial;
+    use std::sync::mpsc;
+    use std::thread;
+
+    // With the normal speed internal clock
+    const TRANSFER_CYCLES: u64 = 4096;
+
+    /// Connects two links over the loopback, on an ephemeral port
+    fn pair() -> (TcpLink, TcpLink) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let connect = thread::spawn(move || TcpLink::connect(addr).unwrap());
+        let accepted = TcpLink::accept(&listener).unwrap();
+
+        (accepted, connect.join().unwrap())
+    }
+
+    /// Runs a transfer with `sb` and `sc`, returns the cycles since the
+    /// connection when it completes and the byte received
+    fn transfer(serial: &mut Serial, cycles: &mut u64, sb: u8, sc: u8) -> (u64, u8) {
+        serial.w(0x01, sb);
+        serial.w(0x02, sc);
+
+        while *cycles < 100_000 {
+            *cycles += 4;
+            if serial.tick(4) {
+                return (*cycles, serial.sb);
+            }
+        }
+
+        panic!("the transfer didn't complete");
+    }
+
+    fn serial(link: TcpLink) -> Serial {
+        let mut serial = Serial::new();
+        serial.device = Box::new(link);
+
+        serial
+    }
+
+    #[test]
+    fn handshake() {
+        for hello in [b"GBLINK\x01", b"GBLINX\x01", b"GBLINK\x02"] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let peer = thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut received = [0; 7];
+                stream.read_exact(&mut received).unwrap();
+                stream.write_all(hello).unwrap();
+
+                received
+            });
+
+            let link = TcpLink::connect(addr);
+            assert_eq!(&peer.join().unwrap(), b"GBLINK\x01");
+
+            if hello == b"GBLINK\x01" {
+                assert!(link.is_ok());
+            } else {
+                let e = link.err().unwrap();
+                assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            }
+        }
+    }
+
+    #[test]
+    fn exchange() {
+        let (master, slave) = pair();
+
+        // Both start right after connecting, the slave waiting on the master.
+        // It stays connected until it is told to leave.
+        let (leave, left) = mpsc::channel();
+        let slave = thread::spawn(move || {
+            let mut serial = serial(slave);
+            let result = transfer(&mut serial, &mut 0, 0x24, 0x80);
+            left.recv().unwrap();
+
+            result
+        });
+
+        let mut serial = serial(master);
+        let mut cycles = 0;
+        assert_eq!(
+            transfer(&mut serial, &mut cycles, 0x42, 0x81),
+            (TRANSFER_CYCLES, 0x24)
+        );
+
+        leave.send(()).unwrap();
+        assert_eq!(slave.join().unwrap(), (TRANSFER_CYCLES, 0x42));
+
+        // The slave is gone, nothing is shifted in anymore
+        assert_eq!(
+            transfer(&mut serial, &mut cycles, 0x42, 0x81),
+            (TRANSFER_CYCLES * 2, 0xff)
+        );
+    }
+}
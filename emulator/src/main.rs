@@ -1,47 +1,45 @@
-pub mod audio;
-pub mod consts;
-pub mod display;
-pub mod gamepad;
-pub mod interrupts_timers;
-pub mod io;
-pub mod opcodes;
-pub mod state;
-
-use crate::gamepad::Gamepad;
-use crate::state::{GBState, MemError};
-use clap::Parser;
-use std::time::SystemTime;
-use std::{thread, time};
-
-pub fn exec_opcode(state: &mut GBState) -> Result<u64, MemError> {
-    let opcode = state.mem.r(state.cpu.pc)?;
-
-    if state.is_debug {
-        println!(
-            "{:02x}:{:04x} = {:02x} (IME: {})",
-            state.mem.rom_bank, state.cpu.pc, opcode, state.mem.ime
-        );
-    }
+use clap::{Parser, Subcommand};
+use emulator::disasm;
+use emulator::link::TcpLink;
+use emulator::screenshot;
+use emulator::trace::Tracer;
+use emulator::{EmuError, Emulator};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-    state.cpu.pc += 1;
-
-    let n1 = (opcode >> 3) & 0b111;
-    let n2 = opcode & 0b111;
-
-    match opcode >> 6 {
-        0b00 => opcodes::op00(state, n1, n2),
-        0b01 => opcodes::op01(state, n1, n2),
-        0b10 => opcodes::op10(state, n1, n2),
-        0b11 => opcodes::op11(state, n1, n2),
-        _ => panic!(),
-    }
-}
+#[cfg(feature = "frontend")]
+use emulator::consts::{
+    CPU_CYCLE_LENGTH_NANOS, CYCLES_PER_FRAME, DISPLAY_UPDATE_SLEEP_TIME_MICROS,
+};
+#[cfg(feature = "frontend")]
+use emulator::frontend::{
+    audio::AudioOutput,
+    config::{Action, Config, DEFAULT_CONFIG_FILE},
+    gamepad::Gamepad,
+    input::Controls,
+    window::Window,
+};
+#[cfg(feature = "frontend")]
+use emulator::rewind::Rewind;
+#[cfg(feature = "frontend")]
+use std::{thread, time};
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// The gameboy rom file
-    rom: String,
+    #[arg(required = true)]
+    rom: Option<String>,
 
     /// Setting this saves battery by using thread::sleep instead of spin_sleeping. It can result in lag and inconsistent timing.
     #[arg(long)]
@@ -49,66 +47,511 @@ struct Cli {
 
     #[arg(short, long, default_value_t = 1.0)]
     speed: f32,
+
+    /// Run without any window, audio device or gamepad, as fast as possible.
+    #[arg(long)]
+    headless: bool,
+
+    /// Stop after this number of frames (runs forever by default). The time a
+    /// frame takes counts as one with the LCD off.
+    #[arg(long)]
+    frames: Option<u64>,
+
+    /// Save a screenshot once this number of frames is reached and exit.
+    #[arg(long, value_name = "FRAME", conflicts_with = "frames")]
+    screenshot: Option<u64>,
+
+    /// Where to save the --screenshot one (next to the ROM by default).
+    #[arg(long, value_name = "FILE", requires = "screenshot")]
+    screenshot_file: Option<String>,
+
+    /// Enlarge the screenshots by this integer factor.
+    #[arg(long, value_name = "FACTOR", default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..))]
+    screenshot_scale: u8,
+
+    /// Start from the save state in this slot.
+    #[arg(long, value_name = "SLOT", value_parser = clap::value_parser!(u8).range(0..STATE_SLOTS as i64))]
+    load_state: Option<u8>,
+
+    /// Start in the command line debugger, stopped before the first instruction.
+    #[arg(long)]
+    debug: bool,
+
+    /// Log every instruction to this file, in the Gameboy Doctor format.
+    #[arg(long, value_name = "FILE")]
+    trace: Option<String>,
+
+    /// Add the number of cycles elapsed to the trace.
+    #[arg(long, requires = "trace")]
+    trace_cycles: bool,
+
+    /// Add the LY register to the trace.
+    #[arg(long, requires = "trace")]
+    trace_ly: bool,
+
+    /// Print the bytes sent through the link port, test ROMs report their results this way.
+    #[arg(long)]
+    serial_stdout: bool,
+
+    /// Wait for another emulator to connect its link cable on this port.
+    #[arg(long, value_name = "PORT", conflicts_with = "link_connect")]
+    link_listen: Option<u16>,
+
+    /// Connect the link cable to another emulator started with --link-listen.
+    #[arg(long, value_name = "HOST:PORT")]
+    link_connect: Option<String>,
+
+    /// TOML file with the input bindings (defaults to ./emulator.toml if it exists).
+    #[cfg(feature = "frontend")]
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Override the bindings of an action, e.g. `--bind a=key:S,button:East`. Can be repeated.
+    #[cfg(feature = "frontend")]
+    #[arg(long, value_name = "ACTION=INPUTS")]
+    bind: Vec<String>,
 }
 
-fn main() {
-    let cli = Cli::parse();
+#[derive(Subcommand)]
+enum Command {
+    /// Print the instructions of a ROM, from the given address to the end of
+    /// its bank or of every bank if there is none.
+    Disasm {
+        /// The gameboy rom file
+        rom: String,
+
+        /// Address to start from, with its ROM bank for 0x4000-0x7fff (e.g. 02:4a30)
+        #[arg(value_name = "[BANK:]ADDR")]
+        location: Option<String>,
+
+        /// Stop after this number of instructions
+        #[arg(short = 'n', long)]
+        count: Option<usize>,
+    },
+}
+
+// Save states are numbered from 0 to 9
+const STATE_SLOTS: u8 = 10;
+
+fn state_file(rom: &str, slot: u8) -> String {
+    format!("{}.ss{}", rom, slot)
+}
+
+#[cfg(feature = "frontend")]
+fn save_state(emulator: &Emulator, rom: &str, slot: u8) {
+    let file = state_file(rom, slot);
+
+    match emulator.save_state_file(&file) {
+        Ok(()) => println!("State saved to slot {} ({:?})", slot, file),
+        Err(e) => println!("Failed to save state to {:?}: {}", file, e),
+    }
+}
+
+fn load_state(emulator: &mut Emulator, rom: &str, slot: u8) -> bool {
+    let file = state_file(rom, slot);
+
+    match emulator.load_state_file(&file) {
+        Ok(()) => {
+            println!("State loaded from slot {} ({:?})", slot, file);
+            true
+        }
+        Err(e) => {
+            println!("Failed to load state from {:?}: {}", file, e);
+            false
+        }
+    }
+}
+
+fn screenshot_file(rom: &str) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    format!("{}.{}.png", rom, screenshot::timestamp(now))
+}
+
+fn save_screenshot(cli: &Cli, emulator: &Emulator, file: &str) -> bool {
+    let screenshot = emulator.screenshot().scale(cli.screenshot_scale as usize);
+
+    match screenshot.save_png(file) {
+        Ok(()) => {
+            println!("Screenshot saved to {:?}", file);
+            true
+        }
+        Err(e) => {
+            println!("Failed to save the screenshot to {:?}: {}", file, e);
+            false
+        }
+    }
+}
+
+/// Takes the --screenshot one, once the emulation is over
+fn save_final_screenshot(cli: &Cli, emulator: &Emulator, rom: &str) {
+    if cli.screenshot.is_none() {
+        return;
+    }
+
+    let file = cli
+        .screenshot_file
+        .clone()
+        .unwrap_or_else(|| screenshot_file(rom));
+
+    if !save_screenshot(cli, emulator, &file) {
+        std::process::exit(1);
+    }
+}
+
+fn setup_debug_tools(cli: &Cli, emulator: &mut Emulator) {
+    if cli.debug {
+        emulator.break_into_debugger();
+    }
+
+    if let Some(file) = &cli.trace {
+        match Tracer::new(file) {
+            Ok(mut tracer) => {
+                tracer.cycles = cli.trace_cycles;
+                tracer.ly = cli.trace_ly;
+                emulator.tracer = Some(tracer);
+            }
+            Err(e) => {
+                eprintln!("Failed to create the trace {:?}: {}", file, e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn print_events(emulator: &mut Emulator) {
+    for event in emulator.take_events() {
+        println!("Emulator event: {}", event);
+    }
+}
+
+fn print_serial_output(cli: &Cli, emulator: &mut Emulator) {
+    let output = emulator.serial_output();
+
+    if cli.serial_stdout && !output.is_empty() {
+        print!("{}", String::from_utf8_lossy(&output));
+        std::io::stdout().flush().ok();
+    }
+}
+
+/// Reports an error of the emulated machine and stops in the debugger when
+/// it's attached, the emulation goes on otherwise
+fn handle_error(emulator: &mut Emulator, e: EmuError) {
+    println!("Emulation error: {}", e);
+
+    if emulator.debugger.is_some() {
+        emulator.break_into_debugger();
+    }
+}
+
+fn connect_link(cli: &Cli, emulator: &mut Emulator) {
+    let link = if let Some(port) = cli.link_listen {
+        println!("Waiting for the link cable on port {}...", port);
+        TcpLink::listen(port)
+    } else if let Some(addr) = &cli.link_connect {
+        println!("Connecting the link cable to {}...", addr);
+        TcpLink::connect(addr.as_str())
+    } else {
+        return;
+    };
+
+    match link {
+        Ok(link) => {
+            println!("Link cable connected");
+            emulator.connect_serial(Box::new(link));
+        }
+        Err(e) => {
+            eprintln!("Failed to connect the link cable: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn load_rom(emulator: &mut Emulator, rom: &str) {
+    if let Err(e) = emulator.load_rom(rom) {
+        eprintln!("Failed to load {:?}: {}", rom, e);
+        std::process::exit(1);
+    }
+}
+
+struct SaveFile {
+    path: String,
+    last_ram_bank_enabled: bool,
+}
+
+impl SaveFile {
+    fn load(rom: &str, emulator: &mut Emulator) -> Self {
+        let path = format!("{}.sav", rom);
+
+        if emulator.state.mem.load_external_ram(&path).is_err() {
+            println!("\"{}\" not found. Initializing new external ram.", path);
+        }
+
+        Self {
+            path,
+            last_ram_bank_enabled: false,
+        }
+    }
+
+    /// Games disable the external RAM once they are done writing to it, which
+    /// is when we save it to disk.
+    fn update(&mut self, emulator: &Emulator) {
+        if self.last_ram_bank_enabled && !emulator.state.mem.ram_enabled() {
+            self.save(emulator);
+        }
+        self.last_ram_bank_enabled = emulator.state.mem.ram_enabled();
+    }
+
+    /// Called on exit, in case the game was still writing to it
+    fn flush(&mut self, emulator: &Emulator) {
+        if emulator.state.mem.ram_enabled() {
+            self.save(emulator);
+        }
+    }
+
+    fn save(&self, emulator: &Emulator) {
+        println!("Saving to \"{}\"...", self.path);
+
+        if emulator.state.mem.save_external_ram(&self.path).is_err() {
+            println!("Failed to save external RAM");
+        }
+    }
+}
+
+fn run_headless(cli: &Cli, rom: &str) {
+    println!("Starting {:?} in headless mode...", rom);
+
+    let mut emulator = Emulator::new();
+
+    load_rom(&mut emulator, rom);
+
+    let mut save_file = SaveFile::load(rom, &mut emulator);
+
+    if let Some(slot) = cli.load_state {
+        if !load_state(&mut emulator, rom, slot) {
+            std::process::exit(1);
+        }
+    }
+
+    setup_debug_tools(cli, &mut emulator);
+    connect_link(cli, &mut emulator);
+
+    while cli
+        .screenshot
+        .or(cli.frames)
+        .is_none_or(|frames| emulator.frames() < frames)
+    {
+        match emulator.run_frame() {
+            Ok(_) => {}
+            Err(EmuError::Quit) => break,
+            Err(e) => handle_error(&mut emulator, e),
+        }
+        emulator.audio_samples();
+        print_events(&mut emulator);
+        print_serial_output(cli, &mut emulator);
+
+        save_file.update(&emulator);
+    }
+
+    save_file.flush(&emulator);
+    save_final_screenshot(cli, &emulator, rom);
+}
+
+#[cfg(feature = "frontend")]
+fn load_config(cli: &Cli) -> Config {
+    let file = match &cli.config {
+        Some(file) => Some(file.as_str()),
+        None if std::path::Path::new(DEFAULT_CONFIG_FILE).exists() => Some(DEFAULT_CONFIG_FILE),
+        None => None,
+    };
+
+    let mut config = match file {
+        Some(file) => {
+            println!("Loading config from {:?}...", file);
+            Config::load(file).unwrap_or_else(|e| {
+                eprintln!("Failed to load config {:?}: {}", file, e);
+                std::process::exit(1);
+            })
+        }
+        None => Config::default(),
+    };
+
+    if let Err(e) = config.apply_overrides(&cli.bind) {
+        eprintln!("Invalid --bind: {}", e);
+        std::process::exit(1);
+    }
+
+    config
+}
+
+#[cfg(feature = "frontend")]
+fn run_frontend(cli: &Cli, rom: &str) {
+    let mut controls = Controls::new(load_config(cli));
 
     println!("Initializing Gamepad...");
 
     let mut gamepad = Gamepad::new();
 
-    println!("Starting {:?}...", &cli.rom);
+    println!("Starting {:?}...", rom);
+
+    let mut emulator = Emulator::new();
 
-    let mut state = GBState::new();
+    let mut window = Window::new();
+    let _audio = AudioOutput::new(emulator.state.mem.audio.source());
 
-    let save_file = format!("{}.sav", &cli.rom);
+    load_rom(&mut emulator, rom);
 
-    state.mem.load_rom(&cli.rom).unwrap();
+    let mut save_file = SaveFile::load(rom, &mut emulator);
 
-    if let Err(_) = state.mem.load_external_ram(&save_file) {
-        println!(
-            "\"{}\" not found. Initializing new external ram.",
-            save_file
-        );
+    let mut state_slot = 0;
+    if let Some(slot) = cli.load_state {
+        if !load_state(&mut emulator, rom, slot) {
+            std::process::exit(1);
+        }
+        state_slot = slot;
     }
 
+    setup_debug_tools(cli, &mut emulator);
+    connect_link(cli, &mut emulator);
+
+    let mut rewind = Rewind::new(
+        controls.config.rewind_memory_mib * 1024 * 1024,
+        controls.config.rewind_interval,
+    );
+
     let mut nanos_sleep: i128 = 0;
 
-    let mut last_ram_bank_enabled = false;
+    let mut last_frame = emulator.state.mem.display.frames;
+    let mut last_window_update = SystemTime::now();
+    let mut paused = false;
+
+    while cli
+        .screenshot
+        .or(cli.frames)
+        .is_none_or(|frames| emulator.frames() < frames)
+    {
+        if paused {
+            // The window still has to be updated to get the keyboard events
+            window.update(emulator.framebuffer());
+            gamepad.update_events();
+            controls.update(&window, &gamepad);
+
+            if controls.just_pressed(Action::Pause) {
+                println!("Resumed");
+                paused = false;
+            }
+
+            thread::sleep(time::Duration::from_micros(
+                DISPLAY_UPDATE_SLEEP_TIME_MICROS,
+            ));
+            continue;
+        }
+
+        if controls.is_pressed(Action::Rewind) {
+            // Goes back one snapshot per `rewind_interval` frames of time, so
+            // the game plays backwards at the emulation speed
+            if let Err(e) = rewind.step_back(&mut emulator) {
+                println!("Failed to rewind: {}", e);
+            }
+            window.update(emulator.framebuffer());
+
+            thread::sleep(
+                time::Duration::from_nanos(
+                    controls.config.rewind_interval * CYCLES_PER_FRAME * CPU_CYCLE_LENGTH_NANOS,
+                )
+                .div_f32(cli.speed),
+            );
+
+            gamepad.update_events();
+            controls.update(&window, &gamepad);
+            nanos_sleep = 0;
+            continue;
+        }
 
-    loop {
         let now = SystemTime::now();
-        let c = if !state.mem.halt {
-            exec_opcode(&mut state).unwrap()
-        } else {
-            4
+        // The cycles are only used for the timing, a failed step is counted
+        // as the shortest one
+        let c = match emulator.step_instruction() {
+            Ok(c) => c,
+            Err(EmuError::Quit) => break,
+            Err(e) => {
+                handle_error(&mut emulator, e);
+                4
+            }
         };
 
-        state.div_timer(c);
-        state.tima_timer(c);
-        state.update_display_interrupts(c);
-        state.check_interrupts().unwrap();
+        if emulator.state.mem.display.frames != last_frame {
+            last_frame = emulator.state.mem.display.frames;
+
+            rewind.update(&emulator);
+
+            if SystemTime::now()
+                .duration_since(last_window_update)
+                .unwrap()
+                .as_micros()
+                > DISPLAY_UPDATE_SLEEP_TIME_MICROS as u128
+            {
+                window.update(emulator.framebuffer());
+                last_window_update = SystemTime::now();
+            }
+        } else if (emulator.state.mem.stopped || !emulator.state.mem.display.lcd_enabled())
+            && SystemTime::now()
+                .duration_since(last_window_update)
+                .unwrap()
+                .as_micros()
+                > DISPLAY_UPDATE_SLEEP_TIME_MICROS as u128
+        {
+            // No frame is coming, the window still has to be updated to get
+            // the keyboard events
+            window.update(emulator.framebuffer());
+            last_window_update = SystemTime::now();
+        }
+
+        print_events(&mut emulator);
+        print_serial_output(cli, &mut emulator);
+
+        let speed = if controls.is_pressed(Action::FastForward) {
+            cli.speed * controls.config.fast_forward_speed
+        } else {
+            cli.speed
+        };
 
-        nanos_sleep += c as i128 * (consts::CPU_CYCLE_LENGTH_NANOS as f32 / cli.speed) as i128;
+        nanos_sleep += c as i128 * (CPU_CYCLE_LENGTH_NANOS as f32 / speed) as i128;
         if nanos_sleep > 0 {
             gamepad.update_events();
+            controls.update(&window, &gamepad);
 
-            let action_button_reg = gamepad.get_action_gamepad_reg();
-            let direction_button_reg = gamepad.get_direction_gamepad_reg();
-            gamepad.check_special_actions(&mut state);
-
-            if state.mem.joypad_is_action
-                && (action_button_reg & (state.mem.joypad_reg >> 4))
-                    != (state.mem.joypad_reg >> 4)
-                || (!state.mem.joypad_is_action
-                    && (direction_button_reg & state.mem.joypad_reg & 0b1111)
-                        != (state.mem.joypad_reg & 0b1111))
-            {
-                state.mem.io[0x0f] |= 0b10000;
+            if controls.just_pressed(Action::Pause) {
+                println!("Paused");
+                paused = true;
+                nanos_sleep = 0;
+            }
+            if controls.just_pressed(Action::NextSlot) {
+                state_slot = (state_slot + 1) % STATE_SLOTS;
+                println!("State slot {} selected", state_slot);
+            }
+            if controls.just_pressed(Action::PreviousSlot) {
+                state_slot = (state_slot + STATE_SLOTS - 1) % STATE_SLOTS;
+                println!("State slot {} selected", state_slot);
+            }
+            if controls.just_pressed(Action::SaveState) {
+                save_state(&emulator, rom, state_slot);
             }
+            if controls.just_pressed(Action::LoadState) {
+                load_state(&mut emulator, rom, state_slot);
+            }
+            if controls.just_pressed(Action::Screenshot) {
+                save_screenshot(cli, &emulator, &screenshot_file(rom));
+            }
+            if controls.just_pressed(Action::Debugger) {
+                println!("Breaking into the debugger, see the terminal");
+                emulator.break_into_debugger();
+            }
+            emulator.state.is_debug = controls.is_pressed(Action::Debug);
 
-            state.mem.joypad_reg = direction_button_reg | (action_button_reg << 4);
+            emulator.set_buttons(controls.get_action_reg(), controls.get_direction_reg());
 
             if cli.thread_sleep {
                 thread::sleep(time::Duration::from_nanos(nanos_sleep as u64 / 10));
@@ -118,17 +561,115 @@ fn main() {
                 {}
             }
 
-            nanos_sleep =
-                nanos_sleep - SystemTime::now().duration_since(now).unwrap().as_nanos() as i128;
+            nanos_sleep -= SystemTime::now().duration_since(now).unwrap().as_nanos() as i128;
+            // Don't try to catch up with more than a frame, e.g. after a
+            // debugger prompt
+            nanos_sleep = nanos_sleep.max(-((CYCLES_PER_FRAME * CPU_CYCLE_LENGTH_NANOS) as i128));
+
+            save_file.update(&emulator);
+        }
+    }
+
+    save_file.flush(&emulator);
+    save_final_screenshot(cli, &emulator, rom);
+}
+
+// ROM banks as mapped by the MBCs, bank 0 at 0x0000 and the others at 0x4000
+const ROM_BANK_SIZE: usize = 0x4000;
 
-            if last_ram_bank_enabled && !state.mem.ram_bank_enabled {
-                println!("Saving to \"{}\"...", save_file);
+fn run_disasm(rom: &str, location: Option<&str>, count: Option<usize>) {
+    let data = std::fs::read(rom).unwrap_or_else(|e| {
+        eprintln!("Failed to read {:?}: {}", rom, e);
+        std::process::exit(1);
+    });
 
-                if let Err(_) = state.mem.save_external_ram(&save_file) {
-                    println!("Failed to save external RAM");
-                }
+    let (bank, addr) = match location {
+        Some(location) => match disasm::parse_location(location) {
+            // Bank 0 is only mapped below 0x4000 and the others above
+            Some((None | Some(0), addr)) if addr < 0x4000 => (Some(0), addr),
+            Some((bank, addr)) if (0x4000..0x8000).contains(&addr) && bank != Some(0) => {
+                (Some(bank.unwrap_or(1)), addr)
             }
-            last_ram_bank_enabled = state.mem.ram_bank_enabled;
+            _ => {
+                eprintln!(
+                    "Invalid address {:?}, expected [bank:]addr in the ROM",
+                    location
+                );
+                std::process::exit(1);
+            }
+        },
+        None => (None, 0),
+    };
+
+    let banks = match bank {
+        Some(bank) => bank..bank + 1,
+        None => 0..data.len().div_ceil(ROM_BANK_SIZE),
+    };
+    let mut count = count.unwrap_or(usize::MAX);
+
+    for bank in banks {
+        let base: u16 = if bank == 0 { 0 } else { 0x4000 };
+        let offset = bank * ROM_BANK_SIZE;
+        let end = data.len().min(offset + ROM_BANK_SIZE);
+
+        if offset >= end {
+            eprintln!("Bank {:02x} is outside of the ROM", bank);
+            std::process::exit(1);
+        }
+
+        println!("; Bank {:02x}", bank);
+
+        let mut i = offset + (addr as usize & (ROM_BANK_SIZE - 1));
+        while i < end && count > 0 {
+            let addr = base + (i - offset) as u16;
+            let instruction = disasm::decode(&data[i..end.min(i + 3)], addr);
+            let length = instruction.length as usize;
+            let bytes: Vec<String> = data[i..end.min(i + length)]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+
+            println!(
+                "{:02x}:{:04x}  {:<8}  {}",
+                bank,
+                addr,
+                bytes.join(" "),
+                instruction
+            );
+
+            i += length;
+            count -= 1;
         }
     }
 }
+
+fn main() {
+    let cli = Cli::parse();
+
+    if let Some(Command::Disasm {
+        rom,
+        location,
+        count,
+    }) = &cli.command
+    {
+        run_disasm(rom, location.as_deref(), *count);
+        return;
+    }
+
+    // Required by clap when there is no subcommand
+    let rom = cli.rom.as_deref().unwrap();
+
+    if cli.headless {
+        run_headless(&cli, rom);
+        return;
+    }
+
+    #[cfg(feature = "frontend")]
+    run_frontend(&cli, rom);
+
+    #[cfg(not(feature = "frontend"))]
+    {
+        eprintln!("This build doesn't include the frontend, run it with --headless.");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,698 @@
+use crate::cartridge::{CartridgeError, Header, MbcKind};
+use crate::rtc::{Rtc, RTC_FOOTER_SIZE};
+use crate::savestate::{Savable, SaveStateError, StateReader, StateWriter};
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+// MBC2 has 512 half-bytes of RAM built in, whatever the header says
+pub const MBC2_RAM_SIZE: usize = 0x200;
+
+/// Memory bank controller of a cartridge. It handles the reads and writes of
+/// the cartridge's address space (ROM in 0x0000-0x7fff, external RAM in
+/// 0xa000-0xbfff); writes to the ROM area set its banking registers. Its
+/// registers go into save states, the ROM and RAM are saved by `Memory`.
+pub trait Mbc: Savable {
+    fn r_rom(&self, rom: &[u8], addr: u16) -> u8;
+
+    fn w_rom(&mut self, addr: u16, value: u8);
+
+    fn r_ram(&self, ram: &[u8], addr: u16) -> u8;
+
+    fn w_ram(&mut self, ram: &mut [u8], addr: u16, value: u8);
+
+    fn ram_enabled(&self) -> bool;
+
+    /// ROM bank currently mapped in 0x4000-0x7fff
+    fn rom_bank(&self) -> usize;
+
+    /// Advances the cartridge's own clock, if it has one
+    fn tick(&mut self, _c: u64) {}
+
+    /// State of the cartridge's clock, saved after the external RAM
+    fn rtc_footer(&self) -> Option<[u8; RTC_FOOTER_SIZE]> {
+        None
+    }
+
+    fn load_rtc_footer(&mut self, _footer: &[u8]) {}
+}
+
+pub fn new_mbc(header: &Header) -> Result<Box<dyn Mbc>, CartridgeError> {
+    let rom_size = header.rom_size;
+    let ram_size = header.ram_size;
+
+    match header.cartridge_type.mbc {
+        MbcKind::None => Ok(Box::new(NoMbc::new(rom_size, ram_size))),
+        MbcKind::Mbc1 => Ok(Box::new(Mbc1::new(rom_size, ram_size))),
+        MbcKind::Mbc2 => Ok(Box::new(Mbc2::new(rom_size))),
+        MbcKind::Mbc3 => Ok(Box::new(Mbc3::new(
+            rom_size,
+            ram_size,
+            header.cartridge_type.timer,
+        ))),
+        MbcKind::Mbc5 => Ok(Box::new(Mbc5::new(
+            rom_size,
+            ram_size,
+            header.cartridge_type.rumble,
+        ))),
+        kind => Err(CartridgeError::Unsupported(format!(
+            "{:?} memory bank controller",
+            kind
+        ))),
+    }
+}
+
+fn rom_byte(rom: &[u8], rom_size: usize, bank: usize, addr: u16) -> u8 {
+    let offset = (bank * ROM_BANK_SIZE + (addr as usize & (ROM_BANK_SIZE - 1))) % rom_size;
+
+    rom.get(offset).copied().unwrap_or(0xff)
+}
+
+fn ram_offset(ram_size: usize, bank: usize, addr: u16) -> Option<usize> {
+    if ram_size == 0 {
+        None
+    } else {
+        Some((bank * RAM_BANK_SIZE + (addr as usize & (RAM_BANK_SIZE - 1))) % ram_size)
+    }
+}
+
+fn ram_byte(ram: &[u8], ram_size: usize, bank: usize, addr: u16) -> u8 {
+    ram_offset(ram_size, bank, addr)
+        .and_then(|offset| ram.get(offset).copied())
+        .unwrap_or(0xff)
+}
+
+fn set_ram_byte(ram: &mut [u8], ram_size: usize, bank: usize, addr: u16, value: u8) {
+    if let Some(b) = ram_offset(ram_size, bank, addr).and_then(|offset| ram.get_mut(offset)) {
+        *b = value;
+    }
+}
+
+/// 32 KiB ROM only cartridges, possibly with up to 8 KiB of RAM
+pub struct NoMbc {
+    rom_size: usize,
+    ram_size: usize,
+}
+
+impl NoMbc {
+    pub fn new(rom_size: usize, ram_size: usize) -> Self {
+        Self { rom_size, ram_size }
+    }
+}
+
+impl Mbc for NoMbc {
+    fn r_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        rom_byte(rom, self.rom_size, (addr as usize) / ROM_BANK_SIZE, addr)
+    }
+
+    fn w_rom(&mut self, _addr: u16, _value: u8) {}
+
+    fn r_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        ram_byte(ram, self.ram_size, 0, addr)
+    }
+
+    fn w_ram(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        set_ram_byte(ram, self.ram_size, 0, addr, value)
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_size != 0
+    }
+
+    fn rom_bank(&self) -> usize {
+        1
+    }
+}
+
+pub struct Mbc1 {
+    rom_size: usize,
+    ram_size: usize,
+
+    ram_enabled: bool,
+
+    // 5 bits register, selects the ROM bank in 0x4000-0x7fff
+    bank1: u8,
+
+    // 2 bits register, selects the RAM bank or the upper bits of the ROM bank
+    bank2: u8,
+
+    // In mode 1, bank2 also applies to 0x0000-0x3fff and to the RAM
+    mode: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom_size: usize, ram_size: usize) -> Self {
+        Self {
+            rom_size,
+            ram_size,
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+        }
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.mode {
+            self.bank2 as usize
+        } else {
+            0
+        }
+    }
+}
+
+impl Mbc for Mbc1 {
+    fn r_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        if addr < 0x4000 {
+            let bank = if self.mode {
+                (self.bank2 as usize) << 5
+            } else {
+                0
+            };
+            rom_byte(rom, self.rom_size, bank, addr)
+        } else {
+            rom_byte(rom, self.rom_size, self.rom_bank(), addr)
+        }
+    }
+
+    fn w_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enabled = value & 0xf == 0xa,
+            0x2000..=0x3fff => {
+                self.bank1 = value & 0b11111;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
+                }
+            }
+            0x4000..=0x5fff => self.bank2 = value & 0b11,
+            _ => self.mode = value & 1 != 0,
+        }
+    }
+
+    fn r_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if self.ram_enabled {
+            ram_byte(ram, self.ram_size, self.ram_bank(), addr)
+        } else {
+            0xff
+        }
+    }
+
+    fn w_ram(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        if self.ram_enabled {
+            set_ram_byte(ram, self.ram_size, self.ram_bank(), addr, value)
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn rom_bank(&self) -> usize {
+        ((self.bank2 as usize) << 5) | self.bank1 as usize
+    }
+}
+
+pub struct Mbc2 {
+    rom_size: usize,
+
+    ram_enabled: bool,
+
+    // 4 bits register
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new(rom_size: usize) -> Self {
+        Self {
+            rom_size,
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn r_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        if addr < 0x4000 {
+            rom_byte(rom, self.rom_size, 0, addr)
+        } else {
+            rom_byte(rom, self.rom_size, self.rom_bank as usize, addr)
+        }
+    }
+
+    fn w_rom(&mut self, addr: u16, value: u8) {
+        if addr >= 0x4000 {
+            return;
+        }
+
+        // Bit 8 of the address selects which register is written
+        if addr & 0x100 == 0 {
+            self.ram_enabled = value & 0xf == 0xa;
+        } else {
+            self.rom_bank = value & 0xf;
+            if self.rom_bank == 0 {
+                self.rom_bank = 1;
+            }
+        }
+    }
+
+    fn r_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        // Only the lower nibble exists, the 512 bytes are echoed in the whole
+        // 0xa000-0xbfff area
+        if self.ram_enabled {
+            ram.get(addr as usize & (MBC2_RAM_SIZE - 1))
+                .map_or(0xff, |b| b | 0xf0)
+        } else {
+            0xff
+        }
+    }
+
+    fn w_ram(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        if self.ram_enabled {
+            if let Some(b) = ram.get_mut(addr as usize & (MBC2_RAM_SIZE - 1)) {
+                *b = value & 0xf;
+            }
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+}
+
+pub struct Mbc3 {
+    rom_size: usize,
+    ram_size: usize,
+
+    ram_enabled: bool,
+
+    // 7 bits register
+    rom_bank: u8,
+
+    // 0x00-0x03 selects a RAM bank, 0x08-0x0c a RTC register
+    ram_bank: u8,
+
+    rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    pub fn new(rom_size: usize, ram_size: usize, timer: bool) -> Self {
+        Self {
+            rom_size,
+            ram_size,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc: if timer { Some(Rtc::new()) } else { None },
+        }
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn r_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        if addr < 0x4000 {
+            rom_byte(rom, self.rom_size, 0, addr)
+        } else {
+            rom_byte(rom, self.rom_size, self.rom_bank as usize, addr)
+        }
+    }
+
+    fn w_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enabled = value & 0xf == 0xa,
+            0x2000..=0x3fff => {
+                self.rom_bank = value & 0b1111111;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5fff => self.ram_bank = value & 0xf,
+            _ => {
+                if let Some(rtc) = self.rtc.as_mut() {
+                    rtc.latch(value);
+                }
+            }
+        }
+    }
+
+    fn r_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xff;
+        }
+
+        match (self.ram_bank, &self.rtc) {
+            (0x00..=0x07, _) => ram_byte(ram, self.ram_size, self.ram_bank as usize, addr),
+            (0x08..=0x0c, Some(rtc)) => rtc.r(self.ram_bank),
+            _ => 0xff,
+        }
+    }
+
+    fn w_ram(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+
+        match (self.ram_bank, self.rtc.as_mut()) {
+            (0x00..=0x07, _) => {
+                set_ram_byte(ram, self.ram_size, self.ram_bank as usize, addr, value)
+            }
+            (0x08..=0x0c, Some(rtc)) => rtc.w(self.ram_bank, value),
+            _ => {}
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn tick(&mut self, c: u64) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.tick(c);
+        }
+    }
+
+    fn rtc_footer(&self) -> Option<[u8; RTC_FOOTER_SIZE]> {
+        self.rtc.as_ref().map(|rtc| rtc.footer())
+    }
+
+    fn load_rtc_footer(&mut self, footer: &[u8]) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.load_footer(footer);
+        }
+    }
+}
+
+pub struct Mbc5 {
+    rom_size: usize,
+    ram_size: usize,
+
+    // On rumble cartridges, bit 3 of the RAM bank register drives the motor
+    rumble: bool,
+
+    ram_enabled: bool,
+
+    // 9 bits register, bank 0 can be mapped in 0x4000-0x7fff
+    rom_bank: u16,
+
+    // 4 bits register
+    ram_bank: u8,
+}
+
+impl Mbc5 {
+    pub fn new(rom_size: usize, ram_size: usize, rumble: bool) -> Self {
+        Self {
+            rom_size,
+            ram_size,
+            rumble,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn r_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        if addr < 0x4000 {
+            rom_byte(rom, self.rom_size, 0, addr)
+        } else {
+            rom_byte(rom, self.rom_size, self.rom_bank as usize, addr)
+        }
+    }
+
+    fn w_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enabled = value & 0xf == 0xa,
+            0x2000..=0x2fff => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3fff => self.rom_bank = (self.rom_bank & 0xff) | ((value as u16 & 1) << 8),
+            0x4000..=0x5fff => {
+                self.ram_bank = if self.rumble {
+                    value & 0b111
+                } else {
+                    value & 0xf
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn r_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if self.ram_enabled {
+            ram_byte(ram, self.ram_size, self.ram_bank as usize, addr)
+        } else {
+            0xff
+        }
+    }
+
+    fn w_ram(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        if self.ram_enabled {
+            set_ram_byte(ram, self.ram_size, self.ram_bank as usize, addr, value)
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+}
+
+impl Savable for NoMbc {
+    fn save(&self, _w: &mut StateWriter) {}
+
+    fn load(&mut self, _r: &mut StateReader) -> Result<(), SaveStateError> {
+        Ok(())
+    }
+}
+
+impl Savable for Mbc1 {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.ram_enabled);
+        w.u8(self.bank1);
+        w.u8(self.bank2);
+        w.bool(self.mode);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.ram_enabled = r.bool()?;
+        self.bank1 = r.u8()?;
+        self.bank2 = r.u8()?;
+        self.mode = r.bool()?;
+
+        Ok(())
+    }
+}
+
+impl Savable for Mbc2 {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.ram_enabled);
+        w.u8(self.rom_bank);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.ram_enabled = r.bool()?;
+        self.rom_bank = r.u8()?;
+
+        Ok(())
+    }
+}
+
+impl Savable for Mbc3 {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.ram_enabled);
+        w.u8(self.rom_bank);
+        w.u8(self.ram_bank);
+        self.rtc.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.ram_enabled = r.bool()?;
+        self.rom_bank = r.u8()?;
+        self.ram_bank = r.u8()?;
+        self.rtc.load(r)?;
+
+        Ok(())
+    }
+}
+
+impl Savable for Mbc5 {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.ram_enabled);
+        w.u16(self.rom_bank);
+        w.u8(self.ram_bank);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.ram_enabled = r.bool()?;
+        self.rom_bank = r.u16()?;
+        self.ram_bank = r.u8()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::CPU_CLOCK_SPEED;
+
+    // Every bank starts with its own number
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+            rom[bank * ROM_BANK_SIZE + 1] = (bank >> 8) as u8;
+        }
+
+        rom
+    }
+
+    fn bank_at(mbc: &dyn Mbc, rom: &[u8], addr: u16) -> usize {
+        mbc.r_rom(rom, addr) as usize | (mbc.r_rom(rom, addr + 1) as usize) << 8
+    }
+
+    #[test]
+    fn mbc1_rom_banking() {
+        let rom = rom(128);
+        let mut mbc = Mbc1::new(rom.len(), 0);
+
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 1);
+
+        mbc.w_rom(0x2000, 0x05);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 5);
+
+        // Bank 0 can't be selected in 0x4000-0x7fff, nor 0x20, 0x40 and 0x60
+        mbc.w_rom(0x2000, 0x00);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 1);
+        mbc.w_rom(0x4000, 0x01);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0x21);
+
+        // Only the 5 low bits of the register exist
+        mbc.w_rom(0x2000, 0xe3);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0x23);
+
+        // Mode 1 also applies the upper bits to 0x0000-0x3fff
+        assert_eq!(bank_at(&mbc, &rom, 0x0000), 0);
+        mbc.w_rom(0x6000, 0x01);
+        assert_eq!(bank_at(&mbc, &rom, 0x0000), 0x20);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0x23);
+    }
+
+    #[test]
+    fn mbc1_ram_banking() {
+        let rom = rom(4);
+        let mut ram = vec![0; 4 * RAM_BANK_SIZE];
+        let mut mbc = Mbc1::new(rom.len(), ram.len());
+
+        // Disabled until 0x0a is written
+        mbc.w_ram(&mut ram, 0xa000, 0x12);
+        assert_eq!(mbc.r_ram(&ram, 0xa000), 0xff);
+        mbc.w_rom(0x0000, 0x0a);
+        assert!(mbc.ram_enabled());
+
+        // The RAM bank is only selected in mode 1
+        mbc.w_rom(0x4000, 0x02);
+        mbc.w_ram(&mut ram, 0xa000, 0x12);
+        assert_eq!(ram[0], 0x12);
+
+        mbc.w_rom(0x6000, 0x01);
+        mbc.w_ram(&mut ram, 0xa001, 0x34);
+        assert_eq!(ram[2 * RAM_BANK_SIZE + 1], 0x34);
+        assert_eq!(mbc.r_ram(&ram, 0xa000), 0);
+
+        mbc.w_rom(0x0000, 0x00);
+        assert_eq!(mbc.r_ram(&ram, 0xa001), 0xff);
+    }
+
+    #[test]
+    fn mbc2() {
+        let rom = rom(16);
+        let mut ram = vec![0; MBC2_RAM_SIZE];
+        let mut mbc = Mbc2::new(rom.len());
+
+        // Bit 8 of the address selects the ROM bank register
+        mbc.w_rom(0x2100, 0x03);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 3);
+        mbc.w_rom(0x0100, 0x00);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 1);
+        mbc.w_rom(0x01ff, 0x1f);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0xf);
+
+        mbc.w_rom(0x0000, 0x0a);
+        assert!(mbc.ram_enabled());
+
+        // Half-bytes, echoed every 512 bytes
+        mbc.w_ram(&mut ram, 0xa010, 0xab);
+        assert_eq!(ram[0x10], 0x0b);
+        assert_eq!(mbc.r_ram(&ram, 0xa010), 0xfb);
+        assert_eq!(mbc.r_ram(&ram, 0xa210), 0xfb);
+        assert_eq!(mbc.r_ram(&ram, 0xbe10), 0xfb);
+    }
+
+    #[test]
+    fn mbc3() {
+        let rom = rom(128);
+        let mut ram = vec![0; 4 * RAM_BANK_SIZE];
+        let mut mbc = Mbc3::new(rom.len(), ram.len(), true);
+
+        mbc.w_rom(0x2000, 0x00);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 1);
+        mbc.w_rom(0x2000, 0xff);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0x7f);
+
+        mbc.w_rom(0x0000, 0x0a);
+        mbc.w_rom(0x4000, 0x03);
+        mbc.w_ram(&mut ram, 0xa000, 0x56);
+        assert_eq!(ram[3 * RAM_BANK_SIZE], 0x56);
+
+        // 0x08-0x0c map the clock registers, read once latched
+        mbc.w_rom(0x4000, 0x08);
+        mbc.w_ram(&mut ram, 0xa000, 42);
+        mbc.tick(CPU_CLOCK_SPEED);
+        assert_eq!(mbc.r_ram(&ram, 0xa000), 42);
+        mbc.w_rom(0x6000, 0x00);
+        mbc.w_rom(0x6000, 0x01);
+        assert_eq!(mbc.r_ram(&ram, 0xa000), 43);
+        assert_eq!(ram[3 * RAM_BANK_SIZE], 0x56);
+    }
+
+    #[test]
+    fn mbc5() {
+        let rom = rom(512);
+        let mut ram = vec![0; 16 * RAM_BANK_SIZE];
+        let mut mbc = Mbc5::new(rom.len(), ram.len(), false);
+
+        // Bank 0 can be mapped in 0x4000-0x7fff
+        mbc.w_rom(0x2000, 0x00);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0);
+
+        mbc.w_rom(0x2000, 0x34);
+        mbc.w_rom(0x3000, 0x01);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0x134);
+        mbc.w_rom(0x2000, 0xff);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0x1ff);
+
+        mbc.w_rom(0x0000, 0x0a);
+        mbc.w_rom(0x4000, 0x0f);
+        mbc.w_ram(&mut ram, 0xa000, 0x78);
+        assert_eq!(ram[15 * RAM_BANK_SIZE], 0x78);
+
+        // The rumble motor takes bit 3 of the RAM bank
+        ram[7 * RAM_BANK_SIZE] = 0x9a;
+        let mut mbc = Mbc5::new(rom.len(), ram.len(), true);
+        mbc.w_rom(0x0000, 0x0a);
+        mbc.w_rom(0x4000, 0x0f);
+        assert_eq!(mbc.r_ram(&ram, 0xa000), 0x9a);
+    }
+}
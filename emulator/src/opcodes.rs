@@ -1,31 +1,85 @@
-use crate::state::{flag, reg, GBState, MemError};
+use crate::disasm;
+use crate::error::EmuError;
+use crate::state::{flag, reg, Event, GBState};
+
+// Duration of the STOP instruction switching the CGB CPU speed
+const SPEED_SWITCH_CYCLES: u64 = 8200;
+
+pub fn exec_opcode(state: &mut GBState) -> u64 {
+    let opcode = state.read(state.cpu.pc);
+
+    if state.is_debug {
+        println!(
+            "{:02x}:{:04x} = {:02x} {} (IME: {})",
+            state.mem.rom_bank(),
+            state.cpu.pc,
+            opcode,
+            disasm::disassemble(&state.mem, state.cpu.pc),
+            state.mem.ime
+        );
+    }
+
+    // After the HALT bug the byte following HALT is read twice
+    if state.mem.halt_bug {
+        state.mem.halt_bug = false;
+    } else {
+        state.cpu.pc += 1;
+    }
+
+    // EI only takes effect after the instruction following it
+    let enable_ime = state.mem.ime_scheduled;
+
+    let n1 = (opcode >> 3) & 0b111;
+    let n2 = opcode & 0b111;
+
+    let cycles = match opcode >> 6 {
+        0b00 => op00(state, n1, n2),
+        0b01 => op01(state, n1, n2),
+        0b10 => op10(state, n1, n2),
+        _ => op11(state, n1, n2),
+    };
+
+    // Unless that instruction was a DI
+    if enable_ime && state.mem.ime_scheduled {
+        state.mem.ime = true;
+        state.mem.ime_scheduled = false;
+    }
+
+    cycles
+}
 
-pub fn r_16b_from_pc(state: &mut GBState) -> Result<u16, MemError> {
-    let p: u16 = state.mem.r(state.cpu.pc)? as u16 | ((state.mem.r(state.cpu.pc + 1)? as u16) << 8);
+pub fn r_16b_from_pc(state: &mut GBState) -> u16 {
+    let p: u16 = state.read(state.cpu.pc) as u16 | ((state.read(state.cpu.pc + 1) as u16) << 8);
     state.cpu.pc += 2;
 
-    Ok(p)
+    p
 }
 
-pub fn r_8b_from_pc(state: &mut GBState) -> Result<u8, MemError> {
-    let p = state.mem.r(state.cpu.pc)?;
+pub fn r_8b_from_pc(state: &mut GBState) -> u8 {
+    let p = state.read(state.cpu.pc);
     state.cpu.pc += 1;
 
-    Ok(p)
+    p
 }
 
-pub fn ldrr(state: &mut GBState, n1: u8, n2: u8) -> Result<(), MemError> {
+pub fn ldrr(state: &mut GBState, n1: u8, n2: u8) {
     // Load a register into another register
     // LD r, r
-    state.w_reg(n1, state.r_reg(n2)?)
+    let n = state.r_reg(n2);
+    state.w_reg(n1, n)
 }
 
-pub fn ldr8(state: &mut GBState, n1: u8) -> Result<u64, MemError> {
+pub fn ldr8(state: &mut GBState, n1: u8) -> u64 {
     // Load an raw 8b value into a register
-    let p = r_8b_from_pc(state)?;
+    let p = r_8b_from_pc(state);
 
-    state.w_reg(n1, p)?;
-    Ok(8)
+    state.w_reg(n1, p);
+
+    if n1 == 0b110 {
+        12
+    } else {
+        8
+    }
 }
 
 pub fn ldrr16(state: &mut GBState, rr: u8, x: u16) {
@@ -33,13 +87,13 @@ pub fn ldrr16(state: &mut GBState, rr: u8, x: u16) {
     state.cpu.w16(rr, x);
 }
 
-pub fn ldnnsp(state: &mut GBState) -> Result<u64, MemError> {
+pub fn ldnnsp(state: &mut GBState) -> u64 {
     // Load SP into an arbitrary position in memory
-    let p = r_16b_from_pc(state)?;
+    let p = r_16b_from_pc(state);
 
-    state.mem.w(p, (state.cpu.sp & 0xff) as u8)?;
-    state.mem.w(p + 1, (state.cpu.sp >> 8) as u8)?;
-    Ok(20)
+    state.write(p, (state.cpu.sp & 0xff) as u8);
+    state.write(p + 1, (state.cpu.sp >> 8) as u8);
+    20
 }
 
 pub fn ldsphl(state: &mut GBState) -> u64 {
@@ -47,48 +101,48 @@ pub fn ldsphl(state: &mut GBState) -> u64 {
     8
 }
 
-pub fn ldnna(state: &mut GBState, nn: u16) -> Result<(), MemError> {
+pub fn ldnna(state: &mut GBState, nn: u16) {
     // Load A into an arbitrary position in memory
-    state.mem.w(nn, state.cpu.r[reg::A as usize])?;
-    Ok(())
+    state.write(nn, state.cpu.r[reg::A as usize]);
 }
 
-pub fn ldann(state: &mut GBState, nn: u16) -> Result<(), MemError> {
+pub fn ldann(state: &mut GBState, nn: u16) {
     // Load A from an arbitrary position in memory
-    state.cpu.r[reg::A as usize] = state.mem.r(nn)?;
-    Ok(())
+    state.cpu.r[reg::A as usize] = state.read(nn);
 }
 
-pub fn push(state: &mut GBState, x: u16) -> Result<(), MemError> {
-    state.cpu.sp -= 2;
+pub fn push(state: &mut GBState, x: u16) {
+    // SP is decremented during an internal cycle, then the high byte is
+    // written first
+    state.tick(4);
 
-    state.mem.w(state.cpu.sp, (x & 0xff) as u8)?;
+    state.cpu.sp -= 1;
+    state.write(state.cpu.sp, (x >> 8) as u8);
 
-    state.mem.w(state.cpu.sp + 1, (x >> 8) as u8)?;
-
-    Ok(())
+    state.cpu.sp -= 1;
+    state.write(state.cpu.sp, (x & 0xff) as u8);
 }
 
-pub fn pop(state: &mut GBState) -> Result<u16, MemError> {
-    let res = state.mem.r(state.cpu.sp)? as u16 | ((state.mem.r(state.cpu.sp + 1)? as u16) << 8);
+pub fn pop(state: &mut GBState) -> u16 {
+    let res = state.read(state.cpu.sp) as u16 | ((state.read(state.cpu.sp + 1) as u16) << 8);
 
     state.cpu.sp += 2;
 
-    Ok(res)
+    res
 }
 
-pub fn jr8(state: &mut GBState) -> Result<u64, MemError> {
+pub fn jr8(state: &mut GBState) -> u64 {
     // Unconditional relative jump
-    let p = r_8b_from_pc(state)?;
+    let p = r_8b_from_pc(state);
 
     state.cpu.pc = (state.cpu.pc as i16 + p as i8 as i16) as u16;
 
-    Ok(12)
+    12
 }
 
-pub fn jrcc8(state: &mut GBState, n1: u8) -> Result<u64, MemError> {
+pub fn jrcc8(state: &mut GBState, n1: u8) -> u64 {
     // Conditional relative jump
-    let p = r_8b_from_pc(state)?;
+    let p = r_8b_from_pc(state);
     let mut cycles = 8;
 
     if state.cpu.check_flag(n1 & 0b11) {
@@ -96,16 +150,16 @@ pub fn jrcc8(state: &mut GBState, n1: u8) -> Result<u64, MemError> {
         state.cpu.pc = (state.cpu.pc as i16 + p as i8 as i16) as u16;
     }
 
-    Ok(cycles)
+    cycles
 }
 
-pub fn jp16(state: &mut GBState) -> Result<u64, MemError> {
+pub fn jp16(state: &mut GBState) -> u64 {
     // Unconditional absolute jump
-    let p = r_16b_from_pc(state)?;
+    let p = r_16b_from_pc(state);
 
     state.cpu.pc = p;
 
-    Ok(16)
+    16
 }
 
 pub fn jphl(state: &mut GBState) -> u64 {
@@ -115,9 +169,9 @@ pub fn jphl(state: &mut GBState) -> u64 {
     4
 }
 
-pub fn jpcc16(state: &mut GBState, n1: u8) -> Result<u64, MemError> {
+pub fn jpcc16(state: &mut GBState, n1: u8) -> u64 {
     // Conditional absolute jump
-    let p = r_16b_from_pc(state)?;
+    let p = r_16b_from_pc(state);
     let mut cycles = 8;
 
     if state.cpu.check_flag(n1 & 0b11) {
@@ -125,57 +179,50 @@ pub fn jpcc16(state: &mut GBState, n1: u8) -> Result<u64, MemError> {
         state.cpu.pc = p;
     }
 
-    Ok(cycles)
+    cycles
 }
 
-pub fn call(state: &mut GBState) -> Result<u64, MemError> {
+pub fn call(state: &mut GBState) -> u64 {
     // Unconditional function call
-    let p = r_16b_from_pc(state)?;
+    let p = r_16b_from_pc(state);
 
-    push(state, state.cpu.pc)?;
+    push(state, state.cpu.pc);
     state.cpu.pc = p;
 
-    Ok(24)
+    24
 }
 
-pub fn callcc(state: &mut GBState, n1: u8) -> Result<u64, MemError> {
+pub fn callcc(state: &mut GBState, n1: u8) -> u64 {
     // Conditional function call
-    let p = r_16b_from_pc(state)?;
+    let p = r_16b_from_pc(state);
     let mut cycles = 12;
 
     if state.cpu.check_flag(n1 & 0b11) {
         cycles += 12;
-        push(state, state.cpu.pc)?;
+        push(state, state.cpu.pc);
         state.cpu.pc = p;
     }
 
-    Ok(cycles)
+    cycles
 }
 
-pub fn ret(state: &mut GBState) -> Result<u64, MemError> {
-    let res = pop(state)?;
+pub fn ret(state: &mut GBState) -> u64 {
+    state.cpu.pc = pop(state);
 
-    if res == 0 {
-        println!("DEBUG: {:?}", state.cpu);
-        panic!("RET to start");
-    }
-
-    state.cpu.pc = res;
-
-    Ok(16)
+    16
 }
 
-pub fn retcc(state: &mut GBState, n1: u8) -> Result<u64, MemError> {
+pub fn retcc(state: &mut GBState, n1: u8) -> u64 {
     let mut cycles = 8;
     if state.cpu.check_flag(n1 & 0b11) {
         cycles += 12;
-        state.cpu.pc = pop(state)?;
+        state.cpu.pc = pop(state);
     }
 
-    Ok(cycles)
+    cycles
 }
 
-pub fn ld00a(state: &mut GBState, n1: u8) -> Result<u64, MemError> {
+pub fn ld00a(state: &mut GBState, n1: u8) -> u64 {
     // Load register A into or from memory pointed by rr (BC, DE or HL(+/-))
     // LD (rr), A
     // LD A, (rr)
@@ -186,11 +233,9 @@ pub fn ld00a(state: &mut GBState, n1: u8) -> Result<u64, MemError> {
     };
 
     if n1 & 0b001 == 1 {
-        state.cpu.r[reg::A as usize] = state.mem.r(state.cpu.r16(ptr_reg))?;
+        state.cpu.r[reg::A as usize] = state.read(state.cpu.r16(ptr_reg));
     } else {
-        state
-            .mem
-            .w(state.cpu.r16(ptr_reg), state.cpu.r[reg::A as usize])?;
+        state.write(state.cpu.r16(ptr_reg), state.cpu.r[reg::A as usize]);
     }
 
     if n1 & 0b110 == 0b100 {
@@ -201,39 +246,49 @@ pub fn ld00a(state: &mut GBState, n1: u8) -> Result<u64, MemError> {
         state.cpu.w16(reg::HL, state.cpu.r16(reg::HL) - 1); // (HL-)
     }
 
-    Ok(8)
+    8
 }
 
-pub fn inc8(state: &mut GBState, n1: u8) -> Result<u64, MemError> {
+pub fn inc8(state: &mut GBState, n1: u8) -> u64 {
     // Increment 8 bit register
-    state.w_reg(n1, state.r_reg(n1)? + 1)?;
+    let n = state.r_reg(n1) + 1;
+    state.w_reg(n1, n);
     state.cpu.r[reg::F as usize] &= !(flag::N | flag::ZF | flag::H);
-    if state.r_reg(n1)? == 0 {
+    if n == 0 {
         state.cpu.r[reg::F as usize] |= flag::ZF;
     }
 
-    if state.r_reg(n1)? & 0xf == 0x0 {
+    if n & 0xf == 0x0 {
         state.cpu.r[reg::F as usize] |= flag::H;
     }
 
-    Ok(4)
+    if n1 == 0b110 {
+        12
+    } else {
+        4
+    }
 }
 
-pub fn dec8(state: &mut GBState, n1: u8) -> Result<u64, MemError> {
+pub fn dec8(state: &mut GBState, n1: u8) -> u64 {
     // Decrement 8 bit register
-    state.w_reg(n1, state.r_reg(n1)? - 1)?;
+    let n = state.r_reg(n1) - 1;
+    state.w_reg(n1, n);
     state.cpu.r[reg::F as usize] |= flag::N;
 
     state.cpu.r[reg::F as usize] &= !(flag::ZF | flag::H);
-    if state.r_reg(n1)? == 0 {
+    if n == 0 {
         state.cpu.r[reg::F as usize] |= flag::ZF;
     }
 
-    if state.r_reg(n1)? & 0xf == 0xf {
+    if n & 0xf == 0xf {
         state.cpu.r[reg::F as usize] |= flag::H;
     }
 
-    Ok(4)
+    if n1 == 0b110 {
+        12
+    } else {
+        4
+    }
 }
 
 pub fn inc16(state: &mut GBState, rr: u8) -> u64 {
@@ -298,21 +353,21 @@ pub fn cpl(state: &mut GBState) {
     state.cpu.r[reg::A as usize] ^= 0xff;
 }
 
-pub fn addsp8(state: &mut GBState) -> Result<u64, MemError> {
-    let n = r_8b_from_pc(state)? as i8;
+pub fn addsp8(state: &mut GBState) -> u64 {
+    let n = r_8b_from_pc(state) as i8;
 
     state.cpu.sp = (state.cpu.sp as i32 + n as i32) as u16;
 
     state.cpu.r[reg::F as usize] &= !(flag::N | flag::H | flag::CY);
 
-    if (state.cpu.sp & 0xff) as i32 + n as i32 & !0xff != 0 {
+    if ((state.cpu.sp & 0xff) as i32 + n as i32) & !0xff != 0 {
         state.cpu.r[reg::F as usize] |= flag::H;
     }
 
     if (state.cpu.sp as i32 + n as i32) & !0xffff != 0 {
         state.cpu.r[reg::F as usize] |= flag::CY;
     }
-    Ok(16)
+    16
 }
 
 pub fn add(state: &mut GBState, x: u8) {
@@ -344,7 +399,7 @@ pub fn addhlrr(state: &mut GBState, rr: u8) -> u64 {
 
     state.cpu.r[reg::F as usize] &= !(flag::N | flag::H | flag::CY);
 
-    if (hl & 0xff) as i32 + n as i32 & !0xff != 0 {
+    if ((hl & 0xff) as i32 + n as i32) & !0xff != 0 {
         state.cpu.r[reg::F as usize] |= flag::H;
     }
 
@@ -389,7 +444,7 @@ pub fn sub(state: &mut GBState, x: u8) {
         state.cpu.r[reg::F as usize] |= flag::CY;
     }
 
-    state.cpu.r[reg::A as usize] = state.cpu.r[reg::A as usize] - x;
+    state.cpu.r[reg::A as usize] -= x;
 
     if state.cpu.r[reg::A as usize] == 0 {
         state.cpu.r[reg::F as usize] |= flag::ZF;
@@ -470,9 +525,9 @@ pub fn cp(state: &mut GBState, x: u8) {
     }
 }
 
-pub fn rlc(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
+pub fn rlc(state: &mut GBState, r_i: u8) {
     // ROTATE LEFT the input register
-    let mut n = state.r_reg(r_i)?;
+    let mut n = state.r_reg(r_i);
     state.cpu.r[reg::F as usize] &= !(flag::H | flag::N | flag::ZF | flag::CY);
     state.cpu.r[reg::F as usize] |= (n >> 7) << 4;
     n <<= 1;
@@ -480,9 +535,9 @@ pub fn rlc(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
     state.w_reg(r_i, n)
 }
 
-pub fn rrc(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
+pub fn rrc(state: &mut GBState, r_i: u8) {
     // ROTATE RIGHT the input register
-    let mut n = state.r_reg(r_i)?;
+    let mut n = state.r_reg(r_i);
     state.cpu.r[reg::F as usize] &= !(flag::H | flag::N | flag::ZF | flag::CY);
     state.cpu.r[reg::F as usize] |= (n & 1) << 4;
     n >>= 1;
@@ -490,9 +545,9 @@ pub fn rrc(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
     state.w_reg(r_i, n)
 }
 
-pub fn rl(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
+pub fn rl(state: &mut GBState, r_i: u8) {
     // ROTATE LEFT THROUGH CARRY the input register
-    let mut n = state.r_reg(r_i)?;
+    let mut n = state.r_reg(r_i);
     let carry = (state.cpu.r[reg::F as usize] & flag::CY) >> 4;
 
     state.cpu.r[reg::F as usize] &= !(flag::H | flag::N | flag::ZF | flag::CY);
@@ -502,9 +557,9 @@ pub fn rl(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
     state.w_reg(r_i, n)
 }
 
-pub fn rr(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
+pub fn rr(state: &mut GBState, r_i: u8) {
     // ROTATE RIGHT THROUGH CARRY the input register
-    let mut n = state.r_reg(r_i)?;
+    let mut n = state.r_reg(r_i);
     let carry = (state.cpu.r[reg::F as usize] & flag::CY) >> 4;
 
     state.cpu.r[reg::F as usize] &= !(flag::H | flag::N | flag::ZF | flag::CY);
@@ -514,9 +569,9 @@ pub fn rr(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
     state.w_reg(r_i, n)
 }
 
-pub fn sla(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
+pub fn sla(state: &mut GBState, r_i: u8) {
     // Shift left Arithmetic (b0=0) the input register
-    let mut n = state.r_reg(r_i)?;
+    let mut n = state.r_reg(r_i);
 
     state.cpu.r[reg::F as usize] &= !(flag::H | flag::N | flag::ZF | flag::CY);
     state.cpu.r[reg::F as usize] |= (n >> 7) << 4;
@@ -529,9 +584,9 @@ pub fn sla(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
     state.w_reg(r_i, n)
 }
 
-pub fn sra(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
+pub fn sra(state: &mut GBState, r_i: u8) {
     // Shift right Arithmetic (b7=b7) the input register
-    let mut n = state.r_reg(r_i)?;
+    let mut n = state.r_reg(r_i);
 
     state.cpu.r[reg::F as usize] &= !(flag::H | flag::N | flag::ZF | flag::CY);
     state.cpu.r[reg::F as usize] |= (n & 0b1) << 4;
@@ -546,9 +601,9 @@ pub fn sra(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
     state.w_reg(r_i, n)
 }
 
-pub fn swap(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
+pub fn swap(state: &mut GBState, r_i: u8) {
     // Swap the high nibble and low nibble
-    let mut n = state.r_reg(r_i)?;
+    let mut n = state.r_reg(r_i);
 
     let nibble_low = n & 0b1111;
     let nibble_high = n >> 4;
@@ -564,9 +619,9 @@ pub fn swap(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
     state.w_reg(r_i, n)
 }
 
-pub fn srl(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
+pub fn srl(state: &mut GBState, r_i: u8) {
     // Shift right Logical (b7=0) the input register
-    let mut n = state.r_reg(r_i)?;
+    let mut n = state.r_reg(r_i);
 
     state.cpu.r[reg::F as usize] &= !(flag::H | flag::N | flag::ZF | flag::CY);
     state.cpu.r[reg::F as usize] |= (n & 0b1) << 4;
@@ -579,123 +634,177 @@ pub fn srl(state: &mut GBState, r_i: u8) -> Result<(), MemError> {
     state.w_reg(r_i, n)
 }
 
-pub fn bit(state: &mut GBState, n1: u8, n2: u8) -> Result<(), MemError> {
-    let z = (((state.r_reg(n2)? >> n1) & 1) ^ 1) << 7;
+pub fn bit(state: &mut GBState, n1: u8, n2: u8) {
+    let z = (((state.r_reg(n2) >> n1) & 1) ^ 1) << 7;
 
     state.cpu.r[reg::F as usize] &= !(flag::N | flag::ZF);
     state.cpu.r[reg::F as usize] |= flag::H | z;
-    Ok(())
 }
 
-pub fn set(state: &mut GBState, n1: u8, n2: u8) -> Result<(), MemError> {
-    state.w_reg(n2, state.r_reg(n2)? | (1 << n1))
+pub fn set(state: &mut GBState, n1: u8, n2: u8) {
+    let n = state.r_reg(n2);
+    state.w_reg(n2, n | (1 << n1))
+}
+
+pub fn res(state: &mut GBState, n1: u8, n2: u8) {
+    let n = state.r_reg(n2);
+    state.w_reg(n2, n & !(1 << n1))
+}
+
+pub fn stop(state: &mut GBState) -> u64 {
+    // STOP is followed by a byte that is ignored
+    state.cpu.pc += 1;
+
+    state.mem.timer.reset_divider();
+
+    if state.mem.cgb_mode && state.mem.speed_switch_armed {
+        state.mem.speed_switch_armed = false;
+        state.mem.double_speed = !state.mem.double_speed;
+        state.events.push(Event::SpeedSwitch {
+            double_speed: state.mem.double_speed,
+        });
+
+        // The CPU is stopped for 2050 M-cycles while the clock switches, with
+        // DIV and the timers frozen
+        state.tick_clock_stopped(SPEED_SWITCH_CYCLES - state.step_cycles);
+        SPEED_SWITCH_CYCLES
+    } else {
+        // The LCD is blank until the CPU is woken up by a button press
+        state.mem.stopped = true;
+        state.mem.display.cls();
+
+        4
+    }
 }
 
-pub fn res(state: &mut GBState, n1: u8, n2: u8) -> Result<(), MemError> {
-    state.w_reg(n2, state.r_reg(n2)? & !(1 << n1))
+pub fn illegal(state: &mut GBState, opcode: u8) -> u64 {
+    // The opcodes with no instruction hang the CPU, it doesn't even handle
+    // the interrupts anymore
+    let addr = state.cpu.pc - 1;
+
+    state.mem.locked = true;
+    state.mem.raise(EmuError::IllegalOpcode {
+        opcode,
+        addr,
+        bank: if (0x4000..0x8000).contains(&addr) {
+            state.mem.rom_bank()
+        } else {
+            0
+        },
+    });
+
+    4
 }
 
-pub fn op00(state: &mut GBState, n1: u8, n2: u8) -> Result<u64, MemError> {
+pub fn halt(state: &mut GBState) -> u64 {
+    // With IME off and an interrupt already pending HALT exits immediately,
+    // and the CPU fails to increment PC after it (HALT bug)
+    if !state.mem.ime && state.pending_interrupts() != 0 {
+        state.mem.halt_bug = true;
+    } else {
+        state.mem.halt = true;
+    }
+
+    4
+}
+
+pub fn op00(state: &mut GBState, n1: u8, n2: u8) -> u64 {
     // Dispatcher for the instructions starting with 0b00 based on their 3 LSB
     match n2 {
         0b000 => match n1 {
-            0b000 => Ok(4),
+            0b000 => 4,
             0b001 => ldnnsp(state),
-            0b010 => todo!("STOP"),
+            0b010 => stop(state),
             0b011 => jr8(state),
             _ => jrcc8(state, n1),
         },
         0b001 => match n1 {
-            0b001 | 0b011 | 0b101 | 0b111 => Ok(addhlrr(state, n1 >> 1)),
-            0b000 | 0b010 | 0b100 | 0b110 => {
-                let p = r_16b_from_pc(state)?;
+            0b001 | 0b011 | 0b101 | 0b111 => addhlrr(state, n1 >> 1),
+            _ => {
+                let p = r_16b_from_pc(state);
                 ldrr16(state, n1 >> 1, p);
-                Ok(12)
+                12
             }
-            _ => panic!(),
         },
         0b010 => ld00a(state, n1),
         0b011 => match n1 {
-            0b001 | 0b011 | 0b101 | 0b111 => Ok(dec16(state, n1 >> 1)),
-            0b000 | 0b010 | 0b100 | 0b110 => Ok(inc16(state, n1 >> 1)),
-            _ => panic!(),
+            0b001 | 0b011 | 0b101 | 0b111 => dec16(state, n1 >> 1),
+            _ => inc16(state, n1 >> 1),
         },
         0b100 => inc8(state, n1),
         0b101 => dec8(state, n1),
         0b110 => ldr8(state, n1),
-        0b111 => {
+        _ => {
             match n1 {
-                0b000 => rlc(state, 7)?,
-                0b001 => rrc(state, 7)?,
-                0b010 => rl(state, 7)?,
-                0b011 => rr(state, 7)?,
+                0b000 => rlc(state, 7),
+                0b001 => rrc(state, 7),
+                0b010 => rl(state, 7),
+                0b011 => rr(state, 7),
                 0b100 => daa(state),
                 0b101 => cpl(state),
                 0b110 => scf(state),
-                0b111 => ccf(state),
-                _ => panic!(),
+                _ => ccf(state),
             };
-            Ok(4)
+            4
         }
-        _ => panic!(),
     }
 }
 
-pub fn op01(state: &mut GBState, n1: u8, n2: u8) -> Result<u64, MemError> {
+pub fn op01(state: &mut GBState, n1: u8, n2: u8) -> u64 {
     // Dispatcher for the instructions starting with 0b01 (LD r,r and HALT)
     if n1 == 0b110 && n2 == 0b110 {
-        state.mem.halt = true;
-        Ok(4)
+        halt(state)
     } else {
-        ldrr(state, n1, n2)?;
+        ldrr(state, n1, n2);
 
         if n1 == 0b110 || n2 == 0b110 {
-            Ok(8)
+            8
         } else {
-            Ok(4)
+            4
         }
     }
 }
 
-pub fn op10(state: &mut GBState, n1: u8, n2: u8) -> Result<u64, MemError> {
+pub fn op10(state: &mut GBState, n1: u8, n2: u8) -> u64 {
     // Dispatcher for the instructions starting with 0b10 (Arithmetic)
+    let p = state.r_reg(n2);
+
     match n1 {
-        0b000 => add(state, state.r_reg(n2)?),
-        0b001 => adc(state, state.r_reg(n2)?),
-        0b010 => sub(state, state.r_reg(n2)?),
-        0b011 => sbc(state, state.r_reg(n2)?),
-        0b100 => and(state, state.r_reg(n2)?),
-        0b101 => xor(state, state.r_reg(n2)?),
-        0b110 => or(state, state.r_reg(n2)?),
-        0b111 => cp(state, state.r_reg(n2)?),
-        _ => panic!(),
+        0b000 => add(state, p),
+        0b001 => adc(state, p),
+        0b010 => sub(state, p),
+        0b011 => sbc(state, p),
+        0b100 => and(state, p),
+        0b101 => xor(state, p),
+        0b110 => or(state, p),
+        _ => cp(state, p),
     }
 
     if n2 == 0b110 {
-        Ok(8)
+        8
     } else {
-        Ok(4)
+        4
     }
 }
 
-pub fn op11(state: &mut GBState, n1: u8, n2: u8) -> Result<u64, MemError> {
+pub fn op11(state: &mut GBState, n1: u8, n2: u8) -> u64 {
     match n2 {
         0b000 => match n1 {
             0b100 => {
-                let n = r_8b_from_pc(state)?;
-                ldnna(state, n as u16 | 0xff00)?;
-                Ok(12)
+                let n = r_8b_from_pc(state);
+                ldnna(state, n as u16 | 0xff00);
+                12
             }
             0b101 => addsp8(state),
             0b110 => {
-                let n = r_8b_from_pc(state)?;
-                ldann(state, n as u16 | 0xff00)?;
-                Ok(12)
+                let n = r_8b_from_pc(state);
+                ldann(state, n as u16 | 0xff00);
+                12
             }
             0b111 => {
-                let n = r_8b_from_pc(state)?;
+                let n = r_8b_from_pc(state);
                 ldrr16(state, reg::HL, n as u16 + state.cpu.sp);
-                Ok(12)
+                12
             }
             _ => retcc(state, n1 & 0b11),
         },
@@ -706,63 +815,66 @@ pub fn op11(state: &mut GBState, n1: u8, n2: u8) -> Result<u64, MemError> {
 
                 ret(state)
             }
-            0b101 => Ok(jphl(state)),
-            0b111 => Ok(ldsphl(state)),
+            0b101 => jphl(state),
+            0b111 => ldsphl(state),
             _ => {
-                let p = pop(state)?;
+                let p = pop(state);
                 state.cpu.r[(n1 >> 1) as usize * 2 + 1] = (p & 0xff) as u8;
                 state.cpu.r[(n1 >> 1) as usize * 2] = (p >> 8) as u8;
-                Ok(12)
+                12
             }
         },
         0b010 => match n1 {
             0b100 => {
-                ldnna(state, state.cpu.r[reg::C as usize] as u16 | 0xff00)?;
-                Ok(8)
+                ldnna(state, state.cpu.r[reg::C as usize] as u16 | 0xff00);
+                8
             }
             0b101 => {
-                let nn = r_16b_from_pc(state)?;
-                ldnna(state, nn)?;
-                Ok(16)
+                let nn = r_16b_from_pc(state);
+                ldnna(state, nn);
+                16
             }
             0b110 => {
-                ldann(state, state.cpu.r[reg::C as usize] as u16 | 0xff00)?;
-                Ok(8)
+                ldann(state, state.cpu.r[reg::C as usize] as u16 | 0xff00);
+                8
             }
             0b111 => {
-                let nn = r_16b_from_pc(state)?;
-                ldann(state, nn)?;
-                Ok(16)
+                let nn = r_16b_from_pc(state);
+                ldann(state, nn);
+                16
             }
             _ => jpcc16(state, n1 & 0b11),
         },
         0b011 => match n1 {
             0b000 => jp16(state),
             0b001 => op_bitwise(state), // Bitwise operations
-            0b010 | 0b011 | 0b100 | 0b101 => unimplemented!(),
+            0b010..=0b101 => illegal(state, 0b11000011 | n1 << 3),
             0b110 => {
                 state.mem.ime = false;
-                Ok(4)
+                state.mem.ime_scheduled = false;
+                4
             }
-            0b111 => {
-                state.mem.ime = true;
-                Ok(4)
+            _ => {
+                state.mem.ime_scheduled = true;
+                4
             }
-            _ => panic!(),
         },
-        0b100 => callcc(state, n1 & 0b11),
+        0b100 => match n1 {
+            0b000..=0b011 => callcc(state, n1),
+            _ => illegal(state, 0b11000100 | n1 << 3),
+        },
         0b101 => match n1 {
             0b001 => call(state),
-            0b011 | 0b101 | 0b111 => unimplemented!(),
+            0b011 | 0b101 | 0b111 => illegal(state, 0b11000101 | n1 << 3),
             _ => {
                 let value = state.cpu.r[(n1 >> 1) as usize * 2 + 1] as u16
                     | ((state.cpu.r[(n1 >> 1) as usize * 2] as u16) << 8);
-                push(state, value)?;
-                Ok(16)
+                push(state, value);
+                16
             }
         },
         0b110 => {
-            let p = r_8b_from_pc(state)?;
+            let p = r_8b_from_pc(state);
 
             match n1 {
                 0b000 => add(state, p),
@@ -772,24 +884,22 @@ pub fn op11(state: &mut GBState, n1: u8, n2: u8) -> Result<u64, MemError> {
                 0b100 => and(state, p),
                 0b101 => xor(state, p),
                 0b110 => or(state, p),
-                0b111 => cp(state, p),
-                _ => panic!(),
+                _ => cp(state, p),
             }
-            Ok(8)
+            8
         }
-        0b111 => {
+        _ => {
             let p = n1 << 3;
 
-            push(state, state.cpu.pc)?;
+            push(state, state.cpu.pc);
             state.cpu.pc = p as u16;
-            Ok(16)
+            16
         } // RST
-        _ => panic!(),
     }
 }
 
-pub fn op_bitwise(state: &mut GBState) -> Result<u64, MemError> {
-    let p = r_8b_from_pc(state)?;
+pub fn op_bitwise(state: &mut GBState) -> u64 {
+    let p = r_8b_from_pc(state);
     let opcode = p >> 6;
     let n1 = p >> 3 & 0b111;
     let n2 = p & 0b111;
@@ -803,17 +913,186 @@ pub fn op_bitwise(state: &mut GBState) -> Result<u64, MemError> {
             0b100 => sla(state, n2),
             0b101 => sra(state, n2),
             0b110 => swap(state, n2),
-            0b111 => srl(state, n2),
-            _ => panic!(),
+            _ => srl(state, n2),
         },
         0b01 => bit(state, n1, n2),
         0b10 => res(state, n1, n2),
-        0b11 => set(state, n1, n2),
-        _ => panic!(),
-    }?;
-    if n2 == 0b110 {
-        Ok(16)
+        _ => set(state, n1, n2),
+    }
+
+    if n2 == 0b110 && opcode == 0b01 {
+        // BIT only reads (HL)
+        12
+    } else if n2 == 0b110 {
+        16
     } else {
-        Ok(8)
+        8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::emulator::Emulator;
+    use crate::state::reg;
+
+    const JOYPAD: u8 = 0b10000;
+
+    /// Runs `program` from WRAM with the joypad interrupt enabled, the only
+    /// one used by the tests
+    fn emulator(program: &[u8]) -> Emulator {
+        let mut emulator = Emulator::new();
+        let state = &mut emulator.state;
+
+        for (i, &byte) in program.iter().enumerate() {
+            state.mem.w(0xc000 + i as u16, byte);
+        }
+        state.cpu.pc = 0xc000;
+        state.cpu.sp = 0xdff0;
+        state.mem.interrupts_register = JOYPAD;
+
+        emulator
+    }
+
+    fn step(emulator: &mut Emulator) -> u64 {
+        emulator.step_instruction().unwrap()
+    }
+
+    /// Address pushed by the last interrupt dispatch
+    fn return_address(emulator: &Emulator) -> u16 {
+        let state = &emulator.state;
+
+        u16::from_le_bytes([state.mem.r(state.cpu.sp), state.mem.r(state.cpu.sp + 1)])
+    }
+
+    #[test]
+    fn halt_waits_for_an_interrupt() {
+        // HALT, INC B
+        let mut emulator = emulator(&[0x76, 0x04]);
+
+        assert_eq!(step(&mut emulator), 4);
+        for _ in 0..10 {
+            assert_eq!(step(&mut emulator), 4);
+            assert!(emulator.state.mem.halt);
+            assert_eq!(emulator.state.cpu.pc, 0xc001);
+        }
+
+        // Requested but not enabled, still halted
+        emulator.state.mem.io[0x0f] = 0b01;
+        assert_eq!(step(&mut emulator), 4);
+        assert!(emulator.state.mem.halt);
+
+        // Left without dispatching the interrupt with IME off
+        emulator.state.mem.io[0x0f] |= JOYPAD;
+        assert_eq!(step(&mut emulator), 4);
+        assert!(!emulator.state.mem.halt);
+        assert_eq!(emulator.state.cpu.pc, 0xc001);
+        assert_eq!(emulator.state.cpu.sp, 0xdff0);
+        assert_eq!(emulator.state.mem.io[0x0f] & JOYPAD, JOYPAD);
+
+        assert_eq!(step(&mut emulator), 4);
+        assert_eq!(emulator.state.cpu.r[reg::B as usize], 1);
+        assert_eq!(emulator.state.cpu.pc, 0xc002);
+    }
+
+    #[test]
+    fn halt_bug() {
+        // HALT, INC B, NOP
+        let mut emulator = emulator(&[0x76, 0x04, 0x00]);
+        emulator.state.mem.io[0x0f] = JOYPAD;
+
+        step(&mut emulator);
+        assert!(!emulator.state.mem.halt);
+        assert!(emulator.state.mem.halt_bug);
+        assert_eq!(emulator.state.cpu.pc, 0xc001);
+
+        // INC B is executed twice
+        step(&mut emulator);
+        assert_eq!(emulator.state.cpu.r[reg::B as usize], 1);
+        assert_eq!(emulator.state.cpu.pc, 0xc001);
+        step(&mut emulator);
+        assert_eq!(emulator.state.cpu.r[reg::B as usize], 2);
+        assert_eq!(emulator.state.cpu.pc, 0xc002);
+        assert!(!emulator.state.mem.halt_bug);
+    }
+
+    #[test]
+    fn ei_halt_returns_to_the_halt() {
+        // EI, HALT, INC B
+        let mut emulator = emulator(&[0xfb, 0x76, 0x04]);
+        emulator.state.mem.io[0x0f] = JOYPAD;
+
+        assert_eq!(step(&mut emulator), 4);
+        assert_eq!(emulator.state.cpu.pc, 0xc001);
+
+        // HALT and the dispatch
+        assert_eq!(step(&mut emulator), 4 + 20);
+        assert_eq!(emulator.state.cpu.pc, 0x60);
+        assert_eq!(return_address(&emulator), 0xc001);
+        assert!(!emulator.state.mem.halt_bug);
+        assert!(!emulator.state.mem.ime);
+        assert_eq!(emulator.state.mem.io[0x0f] & JOYPAD, 0);
+    }
+
+    #[test]
+    fn ei_di() {
+        // EI, DI, NOP
+        let mut emulator = emulator(&[0xfb, 0xf3, 0x00]);
+        emulator.state.mem.io[0x0f] = JOYPAD;
+
+        for pc in 0xc001..=0xc003 {
+            assert_eq!(step(&mut emulator), 4);
+            assert_eq!(emulator.state.cpu.pc, pc);
+            assert!(!emulator.state.mem.ime);
+        }
+        assert_eq!(emulator.state.mem.io[0x0f] & JOYPAD, JOYPAD);
+    }
+
+    #[test]
+    fn ei_takes_effect_after_the_next_instruction() {
+        // EI, INC B, NOP
+        let mut emulator = emulator(&[0xfb, 0x04, 0x00]);
+        emulator.state.mem.io[0x0f] = JOYPAD;
+
+        assert_eq!(step(&mut emulator), 4);
+        assert_eq!(emulator.state.cpu.pc, 0xc001);
+        assert!(!emulator.state.mem.ime);
+
+        // INC B runs, the interrupt is dispatched right after it
+        assert_eq!(step(&mut emulator), 4 + 20);
+        assert_eq!(emulator.state.cpu.r[reg::B as usize], 1);
+        assert_eq!(emulator.state.cpu.pc, 0x60);
+        assert_eq!(return_address(&emulator), 0xc002);
+    }
+
+    #[test]
+    fn speed_switch_freezes_the_timers() {
+        let mut emulator = Emulator::new();
+        let state = &mut emulator.state;
+
+        // STOP in WRAM, with the switch armed through KEY1
+        state.mem.w(0xc000, 0x10);
+        state.cpu.pc = 0xc000;
+        state.mem.cgb_mode = true;
+        state.mem.speed_switch_armed = true;
+
+        // TIMA at 262144 Hz and a serial transfer on the internal clock,
+        // both would complete during the pause
+        state.mem.w(0xff07, 0b101);
+        state.mem.w(0xff01, 0x42);
+        state.mem.w(0xff02, 0x81);
+        // Bit 3 stays low until the reset, which would otherwise increment
+        // TIMA
+        state.mem.timer.divider = 0x1200;
+
+        let c = emulator.step_instruction().unwrap();
+        let state = &emulator.state;
+
+        assert!(state.mem.double_speed);
+        assert_eq!(c, 8200 / 2);
+        // Reset by STOP and left at 0 since
+        assert_eq!(state.mem.timer.divider, 0);
+        assert_eq!(state.mem.timer.tima, 0);
+        assert_eq!(state.mem.r(0xff02) & 0x80, 0x80);
+        assert_eq!(state.mem.io[0x0f] & 0b1100, 0);
     }
 }
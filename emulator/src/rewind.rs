@@ -0,0 +1,405 @@
+use crate::emulator::Emulator;
+use crate::savestate::SaveStateError;
+use std::collections::VecDeque;
+
+// Within a delta, runs of zeros shorter than this are kept in the literals, as
+// splitting the literals costs about as much as the run itself.
+const MIN_ZERO_RUN: usize = 4;
+
+fn write_varint(out: &mut Vec<u8>, mut v: usize) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+/// None when the data ends in the middle or the value overflows
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut v = 0;
+    let mut shift = 0;
+
+    loop {
+        let b = *data.get(*pos)?;
+        *pos += 1;
+        v |= ((b & 0x7f) as usize).checked_shl(shift)?;
+        if b & 0x80 == 0 {
+            return Some(v);
+        }
+        shift += 7;
+    }
+}
+
+/// Run length encoding of the zeros of a XOR delta: a sequence of (number of
+/// zeros, number of literal bytes, literal bytes).
+fn compress(delta: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < delta.len() {
+        let zeros = delta[i..].iter().take_while(|&&b| b == 0).count();
+        i += zeros;
+
+        let literals_start = i;
+        while i < delta.len() {
+            let run = delta[i..]
+                .iter()
+                .take(MIN_ZERO_RUN)
+                .take_while(|&&b| b == 0)
+                .count();
+
+            if run == MIN_ZERO_RUN {
+                break;
+            }
+            i += run.max(1);
+        }
+
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, i - literals_start);
+        out.extend_from_slice(&delta[literals_start..i]);
+    }
+
+    out
+}
+
+/// XORs the delta compressed by `compress` into `data`. None when the delta
+/// is corrupted or longer than `data`, which is then partially modified.
+fn apply_delta(data: &mut [u8], compressed: &[u8]) -> Option<()> {
+    let mut pos = 0;
+    let mut i: usize = 0;
+
+    while pos < compressed.len() {
+        i = i.checked_add(read_varint(compressed, &mut pos)?)?;
+
+        let literals = read_varint(compressed, &mut pos)?;
+        let target = data.get_mut(i..i.checked_add(literals)?)?;
+        let source = compressed.get(pos..pos + literals)?;
+        for (b, d) in target.iter_mut().zip(source) {
+            *b ^= d;
+        }
+
+        i += literals;
+        pos += literals;
+    }
+
+    Some(())
+}
+
+// A snapshot stored as the difference with the snapshot taken after it
+struct Delta {
+    // Length of the snapshot, save states don't always have the same size
+    len: usize,
+    data: Vec<u8>,
+}
+
+/// Ring buffer of save states taken every few frames, to play the game
+/// backwards. Only the newest snapshot is kept whole, each older one is
+/// stored as a compressed XOR with the one following it: most of the memory
+/// doesn't change from one snapshot to the next. The oldest snapshots are
+/// dropped to stay within the memory budget.
+pub struct Rewind {
+    // Maximum memory used by the snapshots, in bytes
+    budget: usize,
+    // Number of frames between two snapshots
+    interval: u64,
+
+    newest: Option<Vec<u8>>,
+    // Oldest first, the last one is relative to `newest`
+    deltas: VecDeque<Delta>,
+    deltas_size: usize,
+
+    last_snapshot_frame: Option<u64>,
+}
+
+impl Rewind {
+    pub fn new(budget: usize, interval: u64) -> Self {
+        Self {
+            budget,
+            interval: interval.max(1),
+            newest: None,
+            deltas: VecDeque::new(),
+            deltas_size: 0,
+            last_snapshot_frame: None,
+        }
+    }
+
+    /// Number of snapshots available
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Memory currently used by the snapshots, in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.deltas_size + self.newest.as_ref().map_or(0, |newest| newest.len())
+    }
+
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        if let Some(newest) = self.newest.take() {
+            let len = newest.len().max(snapshot.len());
+            let mut delta = newest.clone();
+            delta.resize(len, 0);
+
+            for (d, b) in delta.iter_mut().zip(&snapshot) {
+                *d ^= b;
+            }
+
+            let data = compress(&delta);
+            self.deltas_size += data.len();
+            self.deltas.push_back(Delta {
+                len: newest.len(),
+                data,
+            });
+        }
+
+        self.newest = Some(snapshot);
+
+        while self.memory_usage() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.deltas_size -= delta.data.len(),
+                None => {
+                    // A single snapshot doesn't even fit
+                    self.newest = None;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Removes and returns the newest snapshot
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let newest = self.newest.take()?;
+
+        if let Some(delta) = self.deltas.pop_back() {
+            self.deltas_size -= delta.data.len();
+
+            let mut previous = newest.clone();
+            previous.resize(delta.len.max(newest.len()), 0);
+
+            if apply_delta(&mut previous, &delta.data).is_some() {
+                previous.truncate(delta.len);
+                self.newest = Some(previous);
+            } else {
+                // The older snapshots can't be rebuilt without this one
+                self.clear();
+            }
+        }
+
+        Some(newest)
+    }
+
+    /// Drops every snapshot
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.deltas_size = 0;
+    }
+
+    /// To be called after each frame, takes a snapshot every `interval`
+    /// frames. Does nothing with a budget of 0.
+    pub fn update(&mut self, emulator: &Emulator) {
+        if self.budget == 0 {
+            return;
+        }
+
+        let frame = emulator.state.mem.display.frames;
+
+        if self
+            .last_snapshot_frame
+            .is_none_or(|last| frame >= last + self.interval)
+        {
+            self.push(emulator.save_state());
+            self.last_snapshot_frame = Some(frame);
+        }
+    }
+
+    /// Goes back to the newest snapshot. Returns false when there is none
+    /// left. A snapshot failing to load (e.g. taken before another ROM was
+    /// loaded) leaves the emulator untouched and drops the whole history,
+    /// the older snapshots being derived from it.
+    pub fn step_back(&mut self, emulator: &mut Emulator) -> Result<bool, SaveStateError> {
+        match self.pop() {
+            Some(snapshot) => {
+                emulator
+                    .load_state(&snapshot)
+                    .inspect_err(|_| self.clear())?;
+                self.last_snapshot_frame = Some(emulator.state.mem.display.frames);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    fn round_trip(delta: &[u8]) {
+        let compressed = compress(delta);
+        let mut data = vec![0; delta.len()];
+
+        apply_delta(&mut data, &compressed).unwrap();
+        assert_eq!(data, delta);
+    }
+
+    // Zeros except at the given offsets
+    fn sparse(len: usize, ones: &[usize]) -> Vec<u8> {
+        let mut delta = vec![0; len];
+        for &i in ones {
+            delta[i] = 0xa5;
+        }
+        delta
+    }
+
+    #[test]
+    fn varint() {
+        for (v, len) in [(0, 1), (0x7f, 1), (0x80, 2), (0x3fff, 2), (0x4000, 3)] {
+            let mut out = Vec::new();
+            write_varint(&mut out, v);
+            assert_eq!(out.len(), len);
+
+            let mut pos = 0;
+            assert_eq!(read_varint(&out, &mut pos), Some(v));
+            assert_eq!(pos, len);
+        }
+
+        let mut out = Vec::new();
+        write_varint(&mut out, usize::MAX);
+        assert_eq!(read_varint(&out, &mut 0), Some(usize::MAX));
+
+        // Cut before its last byte, or longer than any usize
+        assert_eq!(read_varint(&[0x80], &mut 0), None);
+        assert_eq!(read_varint(&[0xff; 11], &mut 0), None);
+    }
+
+    #[test]
+    fn compress_round_trip() {
+        round_trip(&[]);
+        round_trip(&[0; 1000]);
+        round_trip(&[0xff; 1000]);
+        // Zero runs shorter than MIN_ZERO_RUN, kept in the literals
+        round_trip(&[1, 0, 2, 0, 0, 3, 0, 0, 0, 4]);
+        // Runs at the start and at the end of the buffer
+        round_trip(&sparse(100, &[50]));
+        round_trip(&sparse(100, &[0, 99]));
+        round_trip(&sparse(100, &[96]));
+        round_trip(&[1, 2, 3, 0, 0, 0]);
+
+        // Zero runs and literals lengths around the varint boundaries
+        for len in [0x7f, 0x80, 0x3fff, 0x4000] {
+            round_trip(&sparse(len + 1, &[len]));
+            let mut delta = vec![0xff; len];
+            delta.extend([0; 8]);
+            delta.push(1);
+            round_trip(&delta);
+        }
+    }
+
+    #[test]
+    fn compress_zeros() {
+        assert_eq!(compress(&[0; 1000]), [0xe8, 0x07, 0x00]);
+        assert_eq!(compress(&[0, 0, 0, 0, 1, 0, 0, 0, 0]), [4, 1, 1, 4, 0]);
+    }
+
+    #[test]
+    fn apply_delta_xors() {
+        let base: Vec<u8> = (0..=255).collect();
+        let delta = sparse(256, &[0, 10, 255]);
+        let mut data = base.clone();
+
+        apply_delta(&mut data, &compress(&delta)).unwrap();
+        for i in 0..256 {
+            assert_eq!(data[i], base[i] ^ delta[i]);
+        }
+    }
+
+    #[test]
+    fn apply_delta_bounds() {
+        let mut data = [0; 4];
+
+        // Literals past the end of the data
+        assert_eq!(apply_delta(&mut data, &[3, 2, 1, 1]), None);
+        assert_eq!(apply_delta(&mut data, &[0xff, 0xff, 0x03, 1, 1]), None);
+        // Fewer literals than announced
+        assert_eq!(apply_delta(&mut data, &[0, 3, 1, 1]), None);
+        // Zero run overflowing
+        let mut compressed = Vec::new();
+        write_varint(&mut compressed, usize::MAX);
+        write_varint(&mut compressed, usize::MAX);
+        assert_eq!(apply_delta(&mut data, &compressed), None);
+    }
+
+    #[test]
+    fn push_pop() {
+        let snapshots = [
+            vec![1; 100],
+            sparse(100, &[3]),
+            sparse(120, &[3, 110]),
+            vec![2; 80],
+        ];
+        let mut rewind = Rewind::new(usize::MAX, 1);
+
+        for snapshot in &snapshots {
+            rewind.push(snapshot.clone());
+        }
+        assert_eq!(rewind.len(), 4);
+
+        for snapshot in snapshots.iter().rev() {
+            assert_eq!(rewind.pop().as_ref(), Some(snapshot));
+        }
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.memory_usage(), 0);
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn memory_budget() {
+        // About 10 deltas of a few bytes fit next to the newest snapshot
+        let snapshot = |i: usize| sparse(1000, &[i]);
+        let mut rewind = Rewind::new(1000 + 10 * 6, 1);
+
+        for i in 0..100 {
+            rewind.push(snapshot(i));
+            assert!(rewind.memory_usage() <= 1000 + 10 * 6);
+        }
+
+        // The oldest ones were dropped
+        let len = rewind.len();
+        assert!(len > 1 && len < 100);
+        for i in (100 - len..100).rev() {
+            assert_eq!(rewind.pop(), Some(snapshot(i)));
+        }
+        assert_eq!(rewind.pop(), None);
+
+        // Not even a single snapshot fits
+        let mut rewind = Rewind::new(10, 1);
+        rewind.push(snapshot(0));
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn step_back_invalid_snapshot() {
+        let mut emulator = Emulator::new();
+        let mut rewind = Rewind::new(usize::MAX, 1);
+        rewind.push(emulator.save_state());
+        rewind.push(emulator.save_state());
+
+        // The snapshots were taken without any cartridge
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x138].copy_from_slice(b"GAME");
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        emulator.state.mem.load_cartridge(cartridge).unwrap();
+
+        assert!(matches!(
+            rewind.step_back(&mut emulator),
+            Err(SaveStateError::WrongRom { .. })
+        ));
+        assert!(rewind.is_empty());
+        assert!(!rewind.step_back(&mut emulator).unwrap());
+    }
+}
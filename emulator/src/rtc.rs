@@ -0,0 +1,315 @@
+use crate::consts::CPU_CLOCK_SPEED;
+use crate::savestate::{Savable, SaveStateError, StateReader, StateWriter};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Size of the RTC footer appended to the save file by BGB and VBA-M: the 5
+// current and 5 latched registers as 32 bits little endian values, followed
+// by a 64 bits UNIX timestamp of when the save was written.
+pub const RTC_FOOTER_SIZE: usize = 48;
+
+// Older VBA-M versions only write a 32 bits timestamp
+const RTC_FOOTER_SIZE_SHORT: usize = 44;
+
+mod dh_flags {
+    pub const DAY_HIGH: u8 = 0b1;
+    pub const HALT: u8 = 0b01000000;
+    pub const DAY_CARRY: u8 = 0b10000000;
+}
+
+const S: usize = 0;
+const M: usize = 1;
+const H: usize = 2;
+const DL: usize = 3;
+const DH: usize = 4;
+
+// Writable bits of each register
+const REGISTERS_MASK: [u8; 5] = [0b111111, 0b111111, 0b11111, 0xff, 0b11000001];
+
+/// Real time clock of the MBC3+TIMER cartridges. It is clocked by the
+/// emulated CPU cycles, so it follows the emulation speed, and catches up
+/// with the time spent while the emulator was closed when loading a save.
+#[derive(Debug, Clone)]
+pub struct Rtc {
+    // Seconds, Minutes, Hours, Days low, Days high/Halt/Carry
+    regs: [u8; 5],
+    latched: [u8; 5],
+
+    // Last value written in 0x6000-0x7fff, latching happens on 0x00 then 0x01
+    last_latch_write: u8,
+
+    cycles: u64,
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self {
+            regs: [0; 5],
+            latched: [0; 5],
+            last_latch_write: 0xff,
+            cycles: 0,
+        }
+    }
+
+    fn halted(&self) -> bool {
+        self.regs[DH] & dh_flags::HALT != 0
+    }
+
+    fn days(&self) -> u64 {
+        self.regs[DL] as u64 | (((self.regs[DH] & dh_flags::DAY_HIGH) as u64) << 8)
+    }
+
+    fn set_days(&mut self, days: u64) {
+        if days > 0x1ff {
+            self.regs[DH] |= dh_flags::DAY_CARRY;
+        }
+        self.regs[DL] = (days & 0xff) as u8;
+        self.regs[DH] = (self.regs[DH] & !dh_flags::DAY_HIGH) | ((days >> 8) & 1) as u8;
+    }
+
+    fn tick_second(&mut self) {
+        // Out of range values keep counting up to the register's size and wrap
+        // to 0 without carrying to the next register.
+        self.regs[S] = (self.regs[S] + 1) & REGISTERS_MASK[S];
+        if self.regs[S] != 60 {
+            return;
+        }
+        self.regs[S] = 0;
+
+        self.regs[M] = (self.regs[M] + 1) & REGISTERS_MASK[M];
+        if self.regs[M] != 60 {
+            return;
+        }
+        self.regs[M] = 0;
+
+        self.regs[H] = (self.regs[H] + 1) & REGISTERS_MASK[H];
+        if self.regs[H] != 24 {
+            return;
+        }
+        self.regs[H] = 0;
+
+        self.set_days(self.days() + 1);
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        if self.halted() {
+            return;
+        }
+
+        while seconds > 0 && (self.regs[S] >= 60 || self.regs[M] >= 60 || self.regs[H] >= 24) {
+            self.tick_second();
+            seconds -= 1;
+        }
+
+        let total = self.regs[S] as u64
+            + self.regs[M] as u64 * 60
+            + self.regs[H] as u64 * 3600
+            + self.days() * 86400
+            + seconds;
+
+        self.regs[S] = (total % 60) as u8;
+        self.regs[M] = (total / 60 % 60) as u8;
+        self.regs[H] = (total / 3600 % 24) as u8;
+        self.set_days(total / 86400);
+    }
+
+    pub fn tick(&mut self, c: u64) {
+        if self.halted() {
+            return;
+        }
+
+        self.cycles += c;
+        while self.cycles >= CPU_CLOCK_SPEED {
+            self.cycles -= CPU_CLOCK_SPEED;
+            self.tick_second();
+        }
+    }
+
+    pub fn latch(&mut self, value: u8) {
+        if self.last_latch_write == 0x00 && value == 0x01 {
+            self.latched = self.regs;
+        }
+        self.last_latch_write = value;
+    }
+
+    /// Reads the latched register selected by 0x08-0x0c
+    pub fn r(&self, reg: u8) -> u8 {
+        let i = (reg - 0x08) as usize;
+
+        self.latched[i] & REGISTERS_MASK[i]
+    }
+
+    pub fn w(&mut self, reg: u8, value: u8) {
+        let i = (reg - 0x08) as usize;
+
+        if i == S {
+            self.cycles = 0;
+        }
+        self.regs[i] = value & REGISTERS_MASK[i];
+        self.latched[i] = self.regs[i];
+    }
+
+    pub fn footer(&self) -> [u8; RTC_FOOTER_SIZE] {
+        let mut footer = [0; RTC_FOOTER_SIZE];
+
+        for (i, r) in self.regs.iter().chain(self.latched.iter()).enumerate() {
+            footer[i * 4] = *r;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        footer[40..].copy_from_slice(&timestamp.to_le_bytes());
+
+        footer
+    }
+
+    pub fn load_footer(&mut self, footer: &[u8]) {
+        if footer.len() != RTC_FOOTER_SIZE && footer.len() != RTC_FOOTER_SIZE_SHORT {
+            println!(
+                "Ignoring RTC save data of unexpected size ({} bytes)",
+                footer.len()
+            );
+            return;
+        }
+
+        for i in 0..5 {
+            self.regs[i] = footer[i * 4] & REGISTERS_MASK[i];
+            self.latched[i] = footer[(i + 5) * 4] & REGISTERS_MASK[i];
+        }
+
+        let mut timestamp = [0; 8];
+        timestamp[..footer.len() - 40].copy_from_slice(&footer[40..]);
+        let timestamp = u64::from_le_bytes(timestamp);
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        if now > timestamp {
+            self.advance(now - timestamp);
+        }
+    }
+}
+
+// Unlike the save file footer, save states restore the clock as it was when
+// saved, without catching up with the wall clock.
+impl Savable for Rtc {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.regs);
+        w.bytes(&self.latched);
+        w.u8(self.last_latch_write);
+        w.u64(self.cycles);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        r.bytes(&mut self.regs)?;
+        r.bytes(&mut self.latched)?;
+        self.last_latch_write = r.u8()?;
+        self.cycles = r.u64()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn footer_at(rtc: &Rtc, timestamp: u64) -> [u8; RTC_FOOTER_SIZE] {
+        let mut footer = rtc.footer();
+        footer[40..].copy_from_slice(&timestamp.to_le_bytes());
+        footer
+    }
+
+    #[test]
+    fn footer_round_trip() {
+        let mut rtc = Rtc::new();
+        for (reg, value) in [
+            (0x08, 30),
+            (0x09, 59),
+            (0x0a, 23),
+            (0x0b, 0xff),
+            (0x0c, 0xc1),
+        ] {
+            rtc.w(reg, value);
+        }
+        rtc.latch(0x00);
+        rtc.latch(0x01);
+
+        // Halted, so no time passes while loading it
+        let footer = rtc.footer();
+        assert_eq!(
+            footer[..20],
+            [30, 0, 0, 0, 59, 0, 0, 0, 23, 0, 0, 0, 0xff, 0, 0, 0, 0xc1, 0, 0, 0]
+        );
+        assert_eq!(footer[20..40], footer[..20]);
+        assert!(u64::from_le_bytes(footer[40..].try_into().unwrap()) >= now() - 1);
+
+        let mut loaded = Rtc::new();
+        loaded.load_footer(&footer);
+        assert_eq!(loaded.regs, rtc.regs);
+        assert_eq!(loaded.latched, rtc.latched);
+    }
+
+    #[test]
+    fn short_footer() {
+        let mut rtc = Rtc::new();
+        rtc.w(0x09, 12);
+        rtc.w(0x0c, dh_flags::HALT);
+
+        // 32 bits timestamp
+        let footer = footer_at(&rtc, now());
+        let mut loaded = Rtc::new();
+        loaded.load_footer(&footer[..RTC_FOOTER_SIZE_SHORT]);
+        assert_eq!(loaded.regs, rtc.regs);
+    }
+
+    #[test]
+    fn invalid_footer_size() {
+        let mut rtc = Rtc::new();
+        rtc.w(0x08, 5);
+
+        rtc.load_footer(&[0x3b; 40]);
+        assert_eq!(rtc.regs[S], 5);
+    }
+
+    #[test]
+    fn catches_up_with_the_time_spent_closed() {
+        // 1 day, 1 hour, 1 minute and 1 second ago
+        let footer = footer_at(&Rtc::new(), now() - 90061);
+
+        let mut rtc = Rtc::new();
+        rtc.load_footer(&footer);
+
+        // A second may have passed since `now` was read
+        assert!((1..=2).contains(&rtc.regs[S]));
+        assert_eq!(rtc.regs[M..=DL], [1, 1, 1]);
+        assert_eq!(rtc.regs[DH], 0);
+    }
+
+    #[test]
+    fn day_counter_overflow() {
+        let mut rtc = Rtc::new();
+        rtc.w(0x0a, 23);
+        rtc.w(0x09, 59);
+        rtc.w(0x08, 59);
+        rtc.w(0x0b, 0xff);
+        rtc.w(0x0c, dh_flags::DAY_HIGH);
+
+        rtc.tick(CPU_CLOCK_SPEED);
+        assert_eq!(rtc.regs, [0, 0, 0, 0, dh_flags::DAY_CARRY]);
+    }
+}
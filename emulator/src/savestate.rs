@@ -0,0 +1,240 @@
+use std::fmt;
+
+// Every save state starts with this magic, then the format version
+const MAGIC: &[u8; 8] = b"GBSTATE\0";
+
+// Bump it whenever the layout written by the `Savable` implementations
+// changes, older states are then refused instead of being misread.
+pub const SAVE_STATE_VERSION: u32 = 5;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    Io(std::io::Error),
+    NotASaveState,
+    UnsupportedVersion(u32),
+    // The state was saved with another cartridge, identified by its title and
+    // global checksum
+    WrongRom { title: String, global_checksum: u16 },
+    Truncated,
+    // A size saved in the state doesn't match the running emulator
+    Mismatch(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::Io(e) => write!(f, "{}", e),
+            SaveStateError::NotASaveState => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is not supported (expected version {})",
+                version, SAVE_STATE_VERSION
+            ),
+            SaveStateError::WrongRom {
+                title,
+                global_checksum,
+            } => write!(
+                f,
+                "the save state was made with another cartridge ({:?}, global checksum 0x{:04x})",
+                title, global_checksum
+            ),
+            SaveStateError::Truncated => write!(f, "the save state is truncated"),
+            SaveStateError::Mismatch(what) => {
+                write!(f, "the save state doesn't match the cartridge: {}", what)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+impl From<std::io::Error> for SaveStateError {
+    fn from(e: std::io::Error) -> Self {
+        SaveStateError::Io(e)
+    }
+}
+
+/// Little endian writer the `Savable` implementations write their fields to
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut w = Self { buf: Vec::new() };
+
+        w.bytes(MAGIC);
+        w.u32(SAVE_STATE_VERSION);
+
+        w
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub fn bool(&mut self, v: bool) {
+        self.u8(v as u8);
+    }
+
+    pub fn u16(&mut self, v: u16) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, v: f32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, v: &[u8]) {
+        self.buf.extend_from_slice(v);
+    }
+
+    /// Length prefixed bytes, for data whose size depends on the cartridge
+    pub fn sized_bytes(&mut self, v: &[u8]) {
+        self.u32(v.len() as u32);
+        self.bytes(v);
+    }
+
+    pub fn string(&mut self, v: &str) {
+        self.sized_bytes(v.as_bytes());
+    }
+}
+
+/// Reads back what a `StateWriter` wrote, in the same order
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, SaveStateError> {
+        let mut r = Self { data, pos: 0 };
+
+        if r.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(SaveStateError::NotASaveState);
+        }
+
+        let version = r.u32()?;
+        if version != SAVE_STATE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+
+        Ok(r)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(SaveStateError::Truncated)?;
+
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SaveStateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, SaveStateError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    pub fn bytes(&mut self, v: &mut [u8]) -> Result<(), SaveStateError> {
+        v.copy_from_slice(self.take(v.len())?);
+
+        Ok(())
+    }
+
+    /// Reads bytes written by `StateWriter::sized_bytes`, which must have the
+    /// same size as `v`.
+    pub fn sized_bytes(&mut self, v: &mut [u8], what: &'static str) -> Result<(), SaveStateError> {
+        if self.u32()? as usize != v.len() {
+            return Err(SaveStateError::Mismatch(what));
+        }
+
+        self.bytes(v)
+    }
+
+    /// Checks that everything was read
+    pub fn finish(&self) -> Result<(), SaveStateError> {
+        if self.pos != self.data.len() {
+            return Err(SaveStateError::Mismatch("unexpected data at the end"));
+        }
+
+        Ok(())
+    }
+
+    pub fn string(&mut self) -> Result<String, SaveStateError> {
+        let len = self.u32()? as usize;
+
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+}
+
+/// A part of the emulator state that goes into save states
+pub trait Savable {
+    fn save(&self, w: &mut StateWriter);
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError>;
+}
+
+impl<T: Savable + Default> Savable for Option<T> {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.is_some());
+        if let Some(v) = self {
+            v.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        *self = if r.bool()? {
+            let mut v = T::default();
+            v.load(r)?;
+            Some(v)
+        } else {
+            None
+        };
+
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+use crate::display::COLORS;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::time::Duration;
+
+// The 4 DMG shades as most reference screenshots are made, from white to
+// black
+const DMG_SHADES: [u32; 4] = [0xffffff, 0xaaaaaa, 0x555555, 0x000000];
+
+// Pixels of the diff images
+const DIFF_COLOR: u32 = 0xff0000;
+
+/// UTC date and time down to the millisecond of a time since the Unix epoch,
+/// e.g. 2026-10-18_09-33-12-345, to name the screenshots
+pub fn timestamp(since_epoch: Duration) -> String {
+    let secs = since_epoch.as_secs();
+
+    // Civil date from the days since 1970-01-01, in 400 years eras starting
+    // on March 1st so the leap day ends them
+    let days = secs / 86400 + 719468;
+    let era_day = days % 146097;
+    let era_year = (era_day - era_day / 1460 + era_day / 36524 - era_day / 146096) / 365;
+    let year_day = era_day - (365 * era_year + era_year / 4 - era_year / 100);
+    let month = (5 * year_day + 2) / 153;
+    let day = year_day - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = days / 146097 * 400 + era_year + (month <= 2) as u64;
+
+    format!(
+        "{}-{:02}-{:02}_{:02}-{:02}-{:02}-{:03}",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// 0RGB pixels, like the framebuffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<u32>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Converts a framebuffer to the colors used by the reference screenshots
+    /// of the test ROMs: the DMG palette becomes shades of grey and the 5 bits
+    /// CGB channels are expanded to 8 bits by repeating their high bits.
+    pub fn from_framebuffer(framebuffer: &[u32], cgb_mode: bool) -> Self {
+        let pixels = framebuffer
+            .iter()
+            .map(|&color| {
+                if cgb_mode {
+                    (0..3).fold(0, |pixel, channel| {
+                        let c = (color >> (channel * 8)) & 0xf8;
+                        pixel | (c | c >> 5) << (channel * 8)
+                    })
+                } else {
+                    COLORS
+                        .iter()
+                        .position(|&c| c == color)
+                        .map_or(color, |shade| DMG_SHADES[shade])
+                }
+            })
+            .collect();
+
+        Self::new(160, 144, pixels)
+    }
+
+    /// Enlarged `factor` times, each pixel becoming a square
+    pub fn scale(&self, factor: usize) -> Self {
+        let pixels = self
+            .pixels
+            .chunks_exact(self.width)
+            .flat_map(|line| {
+                let line: Vec<u32> = line
+                    .iter()
+                    .flat_map(|&pixel| std::iter::repeat_n(pixel, factor))
+                    .collect();
+                std::iter::repeat_n(line, factor).flatten()
+            })
+            .collect();
+
+        Self::new(self.width * factor, self.height * factor, pixels)
+    }
+
+    pub fn load_png(file: &str) -> io::Result<Self> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(file)?));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+        let mut reader = decoder.read_info()?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data)?;
+
+        let bytes_per_pixel = info.color_type.samples();
+        let pixels = data[..info.buffer_size()]
+            .chunks_exact(bytes_per_pixel)
+            .map(|pixel| match info.color_type {
+                png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => {
+                    u32::from_be_bytes([0, pixel[0], pixel[0], pixel[0]])
+                }
+                _ => u32::from_be_bytes([0, pixel[0], pixel[1], pixel[2]]),
+            })
+            .collect();
+
+        Ok(Self::new(info.width as usize, info.height as usize, pixels))
+    }
+
+    pub fn save_png(&self, file: &str) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(File::create(file)?),
+            self.width as u32,
+            self.height as u32,
+        );
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let data: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_be_bytes()[1..].to_vec())
+            .collect();
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+
+        Ok(())
+    }
+
+    /// Number of pixels that differ from `expected`, with an image of them in
+    /// red over a faded `expected`. None when the sizes don't match.
+    pub fn diff(&self, expected: &Image) -> Option<(usize, Image)> {
+        if self.width != expected.width || self.height != expected.height {
+            return None;
+        }
+
+        let mut count = 0;
+        let pixels = self
+            .pixels
+            .iter()
+            .zip(&expected.pixels)
+            .map(|(&actual, &expected)| {
+                if actual == expected {
+                    // Blended with white to make the differences stand out
+                    (expected >> 2 & 0x3f3f3f) + 0xbfbfbf
+                } else {
+                    count += 1;
+                    DIFF_COLOR
+                }
+            })
+            .collect();
+
+        Some((count, Self::new(self.width, self.height, pixels)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("emulator-{}-{}.png", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn timestamp_at(secs: u64, millis: u64) -> String {
+        timestamp(Duration::from_secs(secs) + Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(timestamp_at(0, 0), "1970-01-01_00-00-00-000");
+        assert_eq!(timestamp_at(1760779992, 345), "2025-10-18_09-33-12-345");
+
+        // Year boundaries
+        assert_eq!(timestamp_at(946684799, 999), "1999-12-31_23-59-59-999");
+        assert_eq!(timestamp_at(946684800, 0), "2000-01-01_00-00-00-000");
+
+        // Leap days, 2000 is a leap year but not 2100
+        assert_eq!(timestamp_at(951782400, 0), "2000-02-29_00-00-00-000");
+        assert_eq!(
+            timestamp_at(1709164800 + 86399, 0),
+            "2024-02-29_23-59-59-000"
+        );
+        assert_eq!(timestamp_at(1709251200, 0), "2024-03-01_00-00-00-000");
+        assert_eq!(timestamp_at(4107456000, 0), "2100-02-28_00-00-00-000");
+        assert_eq!(
+            timestamp_at(4107456000 + 86400, 0),
+            "2100-03-01_00-00-00-000"
+        );
+    }
+
+    #[test]
+    fn from_framebuffer() {
+        let mut framebuffer = [COLORS[0]; 160 * 144];
+        framebuffer[1] = COLORS[1];
+        framebuffer[2] = COLORS[2];
+        framebuffer[3] = COLORS[3];
+
+        let image = Image::from_framebuffer(&framebuffer, false);
+        assert_eq!((image.width, image.height), (160, 144));
+        assert_eq!(image.pixels[..4], DMG_SHADES);
+        assert_eq!(image.pixels[4], DMG_SHADES[0]);
+
+        // 5 bits channels, in the high bits of each byte
+        framebuffer[0] = 0xf80800;
+        let image = Image::from_framebuffer(&framebuffer, true);
+        assert_eq!(image.pixels[0], 0xff0800);
+    }
+
+    #[test]
+    fn scale() {
+        let image = Image::new(2, 1, vec![1, 2]).scale(2);
+
+        assert_eq!((image.width, image.height), (4, 2));
+        assert_eq!(image.pixels, [1, 1, 2, 2, 1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn diff() {
+        let expected = Image::new(2, 2, vec![0x000000, 0xffffff, 0x123456, 0x000000]);
+        let actual = Image::new(2, 2, vec![0x000000, 0xff0000, 0x123456, 0x010101]);
+
+        let (count, diff) = actual.diff(&expected).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(diff.pixels[1], DIFF_COLOR);
+        assert_eq!(diff.pixels[3], DIFF_COLOR);
+        // The matching pixels are faded, black becomes light grey
+        assert_eq!(diff.pixels[0], 0xbfbfbf);
+
+        assert_eq!(expected.diff(&expected).unwrap().0, 0);
+        assert!(actual.diff(&actual.scale(2)).is_none());
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let file = temp_file("round-trip");
+        let image = Image::new(
+            3,
+            2,
+            vec![0x000000, 0xffffff, 0xff0000, 0x00ff00, 0x0000ff, 0x123456],
+        );
+
+        image.save_png(&file).unwrap();
+        let loaded = Image::load_png(&file);
+        std::fs::remove_file(&file).ok();
+
+        assert_eq!(loaded.unwrap(), image);
+    }
+
+    #[test]
+    fn load_grayscale_png() {
+        let file = temp_file("grayscale");
+
+        let mut encoder = png::Encoder::new(File::create(&file).unwrap(), 2, 1);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&[0x00, 0xaa])
+            .unwrap();
+
+        let loaded = Image::load_png(&file);
+        std::fs::remove_file(&file).ok();
+
+        assert_eq!(loaded.unwrap().pixels, [0x000000, 0xaaaaaa]);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::savestate::{Savable, SaveStateError, StateReader, StateWriter};
+use std::collections::VecDeque;
+
+// CPU cycles to shift a whole byte with the internal clock, at 8192Hz or
+// 262144Hz in CGB fast mode
+const TRANSFER_CYCLES: u64 = 4096;
+const FAST_TRANSFER_CYCLES: u64 = 128;
+
+// Bytes sent kept until `take_output` is called, the oldest ones are dropped
+const OUTPUT_LIMIT: usize = 0x10000;
+
+mod sc_flags {
+    pub const TRANSFER: u8 = 0b10000000;
+    pub const FAST_CLOCK: u8 = 0b10;
+    pub const INTERNAL_CLOCK: u8 = 0b1;
+}
+
+/// What the serial port is doing, as seen from the link cable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    // No transfer enabled
+    Idle,
+    // Transfer clocked by this Gameboy
+    Internal,
+    // Transfer waiting for the peer's clock
+    External,
+}
+
+/// Whatever is plugged in the link port
+pub trait SerialDevice {
+    /// A transfer clocked by the Gameboy starts, `transfer` is called once
+    /// it completes `cycles` CPU cycles later
+    fn start(&mut self, _byte: u8, _cycles: u64) {}
+
+    /// Transfer clocked by the Gameboy: `byte` is shifted out and the byte
+    /// returned is shifted in.
+    fn transfer(&mut self, byte: u8) -> u8;
+
+    /// Called as the CPU runs, with the cycles elapsed and the state of the
+    /// port. Returns the byte shifted in when the peer clocked a transfer,
+    /// which only completes one waiting on the external clock.
+    fn tick(&mut self, _c: u64, _sb: u8, _clock: Clock) -> Option<u8> {
+        None
+    }
+}
+
+/// Nothing connected: the input line is pulled up and the external clock
+/// never ticks.
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn transfer(&mut self, _byte: u8) -> u8 {
+        0xff
+    }
+}
+
+/// Serial controller, SB (0xff01) and SC (0xff02)
+pub struct Serial {
+    pub sb: u8,
+
+    pub sc: u8,
+
+    // Left before the transfer clocked by the Gameboy completes
+    cycles_left: u64,
+
+    // Every byte sent, test ROMs print their results this way. Not saved in
+    // the states.
+    output: VecDeque<u8>,
+
+    // Not saved in the states
+    pub device: Box<dyn SerialDevice>,
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Self {
+            sb: 0,
+            sc: 0,
+            cycles_left: 0,
+            output: VecDeque::new(),
+            device: Box::new(Disconnected),
+        }
+    }
+
+    pub fn r(&self, addr: u8) -> u8 {
+        match addr {
+            0x01 => self.sb,
+            _ => self.sc | 0b01111100,
+        }
+    }
+
+    pub fn w(&mut self, addr: u8, value: u8) {
+        match addr {
+            0x01 => self.sb = value,
+            _ => {
+                self.sc = value & 0b10000011;
+
+                if self.clock() == Clock::Internal {
+                    self.cycles_left = if self.sc & sc_flags::FAST_CLOCK != 0 {
+                        FAST_TRANSFER_CYCLES
+                    } else {
+                        TRANSFER_CYCLES
+                    };
+                    self.device.start(self.sb, self.cycles_left);
+                }
+            }
+        }
+    }
+
+    pub fn clock(&self) -> Clock {
+        if self.sc & sc_flags::TRANSFER == 0 {
+            Clock::Idle
+        } else if self.sc & sc_flags::INTERNAL_CLOCK != 0 {
+            Clock::Internal
+        } else {
+            Clock::External
+        }
+    }
+
+    /// Advances the transfer in progress by `c` CPU cycles. Returns true
+    /// when it completes, requesting the serial interrupt.
+    pub fn tick(&mut self, c: u64) -> bool {
+        let clock = self.clock();
+        let received = self.device.tick(c, self.sb, clock);
+
+        let sent = self.sb;
+
+        match clock {
+            Clock::Idle => return false,
+            Clock::Internal => {
+                self.cycles_left = self.cycles_left.saturating_sub(c);
+                if self.cycles_left > 0 {
+                    return false;
+                }
+
+                self.sb = self.device.transfer(self.sb);
+            }
+            Clock::External => match received {
+                Some(byte) => self.sb = byte,
+                None => return false,
+            },
+        }
+
+        self.output.push_back(sent);
+        if self.output.len() > OUTPUT_LIMIT {
+            self.output.pop_front();
+        }
+
+        self.sc &= !sc_flags::TRANSFER;
+        true
+    }
+
+    /// Drains the bytes sent since the last call
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.output.drain(..).collect()
+    }
+}
+
+impl Savable for Serial {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.sb);
+        w.u8(self.sc);
+        w.u64(self.cycles_left);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.sb = r.u8()?;
+        self.sc = r.u8()?;
+        self.cycles_left = r.u64()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::GBState;
+
+    // Clocks a transfer with `byte` when asked to
+    struct Peer {
+        byte: Option<u8>,
+    }
+
+    impl SerialDevice for Peer {
+        fn transfer(&mut self, _byte: u8) -> u8 {
+            0xff
+        }
+
+        fn tick(&mut self, _c: u64, _sb: u8, clock: Clock) -> Option<u8> {
+            if clock == Clock::External {
+                self.byte.take()
+            } else {
+                None
+            }
+        }
+    }
+
+    fn transfer_cycles(sc: u8) -> u64 {
+        let mut serial = Serial::new();
+        serial.w(0x01, 0x42);
+        serial.w(0x02, sc);
+
+        let mut cycles = 0;
+        while !serial.tick(4) {
+            cycles += 4;
+            assert_eq!(serial.clock(), Clock::Internal);
+        }
+
+        // Nothing connected, 0xff is shifted in
+        assert_eq!(serial.sb, 0xff);
+        assert_eq!(serial.clock(), Clock::Idle);
+        assert_eq!(serial.take_output(), [0x42]);
+
+        cycles + 4
+    }
+
+    #[test]
+    fn internal_clock() {
+        assert_eq!(transfer_cycles(0x81), TRANSFER_CYCLES);
+        assert_eq!(transfer_cycles(0x83), FAST_TRANSFER_CYCLES);
+    }
+
+    #[test]
+    fn serial_interrupt() {
+        let mut state = GBState::new();
+        state.mem.w(0xff02, 0x81);
+
+        state.tick(TRANSFER_CYCLES - 4);
+        assert_eq!(state.mem.io[0x0f] & 0b1000, 0);
+        state.tick(4);
+        assert_eq!(state.mem.io[0x0f] & 0b1000, 0b1000);
+    }
+
+    #[test]
+    fn external_clock() {
+        let mut serial = Serial::new();
+        serial.w(0x01, 0x42);
+        serial.w(0x02, 0x80);
+
+        // Waits for the peer for as long as it takes
+        for _ in 0..1000 {
+            assert!(!serial.tick(TRANSFER_CYCLES));
+        }
+        assert_eq!(serial.clock(), Clock::External);
+
+        serial.device = Box::new(Peer { byte: Some(0x24) });
+        assert!(serial.tick(4));
+        assert_eq!(serial.sb, 0x24);
+        assert_eq!(serial.clock(), Clock::Idle);
+        assert_eq!(serial.take_output(), [0x42]);
+    }
+
+    #[test]
+    fn sc_unused_bits() {
+        let mut serial = Serial::new();
+
+        serial.w(0x02, 0xff);
+        assert_eq!(serial.sc, 0x83);
+        assert_eq!(serial.r(0x02), 0xff);
+
+        serial.w(0x02, 0x00);
+        assert_eq!(serial.r(0x02), 0x7c);
+
+        serial.w(0x02, 0x01);
+        assert_eq!(serial.r(0x02), 0x7d);
+    }
+}
@@ -1,6 +1,14 @@
 use crate::audio::Audio;
+use crate::cartridge::{Cartridge, CartridgeError, Header, MbcKind};
 use crate::consts::{PROGRAM_START_ADDRESS, STACK_START_ADDRESS};
+use crate::debugger::{Watchpoint, WatchpointHit};
 use crate::display::Display;
+use crate::error::EmuError;
+use crate::mbc::{self, Mbc, NoMbc, MBC2_RAM_SIZE};
+use crate::savestate::{Savable, SaveStateError, StateReader, StateWriter};
+use crate::serial::Serial;
+use crate::timer::Timer;
+use std::cell::RefCell;
 use std::fs::File;
 use std::io::{Read, Write};
 
@@ -44,6 +52,12 @@ pub struct CPU {
     pub sp: u16, // stack pointer
 }
 
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CPU {
     pub fn new() -> Self {
         Self {
@@ -56,9 +70,9 @@ impl CPU {
 
     pub fn r16(&self, r: u8) -> u16 {
         if r == reg::SP {
-            return self.sp;
+            self.sp
         } else {
-            return self.r[r as usize * 2 + 1] as u16 | ((self.r[r as usize * 2] as u16) << 8);
+            self.r[r as usize * 2 + 1] as u16 | ((self.r[r as usize * 2] as u16) << 8)
         }
     }
 
@@ -78,8 +92,7 @@ impl CPU {
             flag::NZ => f >> 7 == 0,
             flag::Z => f >> 7 == 1,
             flag::NC => (f >> 4) & 1 == 0,
-            flag::C => (f >> 4) & 1 == 1,
-            _ => unimplemented!(),
+            _ => (f >> 4) & 1 == 1,
         }
     }
 }
@@ -99,14 +112,13 @@ pub struct Memory {
 
     pub boot_rom_on: bool,
 
-    pub rom_bank: u8,
-
-    pub ram_bank: u8,
+    mbc: Box<dyn Mbc>,
 
-    pub ram_bank_enabled: bool,
+    // Header of the loaded cartridge, if any
+    pub header: Option<Header>,
 
-    // 32 KiB ROM bank 00
-    rom: [u8; 0x200000],
+    // Whole cartridge ROM, banked by the MBC
+    rom: Vec<u8>,
 
     // 4 KiB Work RAM 00
     wram_00: [u8; 0x1000],
@@ -114,8 +126,8 @@ pub struct Memory {
     // 4 KiB Work RAM 00
     wram_01: [u8; 0x1000],
 
-    // External RAM
-    external_ram: [u8; 0x8000],
+    // External RAM, sized from the cartridge header
+    external_ram: Vec<u8>,
 
     // 8 KiB Video RAM
     pub display: Display,
@@ -129,7 +141,12 @@ pub struct Memory {
 
     pub ime: bool,
 
-    pub div: u8,
+    // Set by EI, IME is enabled after the next instruction
+    pub ime_scheduled: bool,
+
+    pub timer: Timer,
+
+    pub serial: Serial,
 
     pub joypad_reg: u8,
 
@@ -139,20 +156,56 @@ pub struct Memory {
 
     pub halt: bool,
 
-    pub tima: u8,
+    // HALT was executed with IME off and an interrupt pending, the next
+    // opcode fetch doesn't increment PC
+    pub halt_bug: bool,
+
+    // Low power mode entered by STOP, left when a button is pressed
+    pub stopped: bool,
+
+    // The CPU hangs after an illegal opcode, only a reset gets it back
+    pub locked: bool,
 
-    pub tma: u8,
+    // CGB double speed mode, switched by STOP once armed through KEY1
+    pub double_speed: bool,
 
-    pub timer_enabled: bool,
+    pub speed_switch_armed: bool,
 
-    pub timer_speed: u8,
+    // Set from the debugger, checked on every read and write
+    pub watchpoints: Vec<Watchpoint>,
+
+    // Collected until the debugger takes them, `r` only borrows the memory
+    watchpoint_hits: RefCell<Vec<WatchpointHit>>,
+
+    // First error raised during the current instruction, returned by
+    // `Emulator::step_instruction` once it's done
+    fault: RefCell<Option<EmuError>>,
 }
 
-#[derive(Debug)]
-pub enum MemError {
-    WritingToROM,
-    Unimplemented,
-    NotUsable,
+/// Something notable happened in the emulated machine, see
+/// `Emulator::take_events`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    // STOP switched the CGB CPU speed
+    SpeedSwitch { double_speed: bool },
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Event::SpeedSwitch { double_speed } => write!(
+                f,
+                "switched to {} speed",
+                if *double_speed { "double" } else { "normal" }
+            ),
+        }
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Memory {
@@ -169,27 +222,32 @@ impl Memory {
             bgcram_pointer_autoincrement: false,
             obcram_pointer: 0,
             obcram_pointer_autoincrement: false,
-            rom_bank: 1,
-            ram_bank: 0,
-            ram_bank_enabled: false,
-            rom: [0; 0x200000],
+            mbc: Box::new(NoMbc::new(0x8000, 0)),
+            header: None,
+            rom: vec![0; 0x8000],
             wram_00: [0; 0x1000],
             wram_01: [0; 0x1000],
-            external_ram: [0; 0x8000],
+            external_ram: Vec::new(),
             display,
             io: [0; 0x80],
             hram: [0; 0x7f],
             audio: Audio::new(),
             ime: false,
+            ime_scheduled: false,
             interrupts_register: 0,
             joypad_is_action: false,
-            joypad_reg: 0,
-            div: 0,
+            joypad_reg: 0xff,
+            timer: Timer::new(),
+            serial: Serial::new(),
             halt: false,
-            tima: 0,
-            tma: 0,
-            timer_enabled: false,
-            timer_speed: 0,
+            halt_bug: false,
+            stopped: false,
+            locked: false,
+            double_speed: false,
+            speed_switch_armed: false,
+            watchpoints: Vec::new(),
+            watchpoint_hits: RefCell::new(Vec::new()),
+            fault: RefCell::new(None),
         }
     }
 
@@ -205,15 +263,40 @@ impl Memory {
         self.boot_rom[..0x900].copy_from_slice(bytes);
     }
 
-    pub fn load_rom(&mut self, file: &str) -> Result<(), std::io::Error> {
-        let mut f = File::open(file)?;
+    /// Blank memory with the same MBC and external RAM size as this one, to
+    /// decode a save state without altering it
+    fn scratch(&self) -> Self {
+        let mut mem = Self::new();
+
+        // The header was already accepted when the cartridge was loaded
+        if let Some(mbc) = self.header.as_ref().and_then(|h| mbc::new_mbc(h).ok()) {
+            mem.mbc = mbc;
+        }
+        mem.external_ram = vec![0; self.external_ram.len()];
+
+        mem
+    }
 
-        f.read(&mut self.rom)?;
+    pub fn load_rom(&mut self, file: &str) -> Result<(), CartridgeError> {
+        self.load_cartridge(Cartridge::from_file(file)?)
+    }
 
-        println!("MBC: {:02x}", self.rom[0x147]);
-        println!("CGB: {:02x}", self.rom[0x143]);
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) -> Result<(), CartridgeError> {
+        println!("Cartridge: {}", cartridge.header);
 
-        if self.rom[0x143] == 0x80 || self.rom[0x143] == 0xc0 {
+        if !cartridge.header_checksum_valid() {
+            println!("Warning: invalid header checksum, a real Gameboy would refuse to boot it");
+        }
+        if !cartridge.global_checksum_valid() {
+            println!("Warning: invalid global checksum");
+        }
+
+        self.mbc = mbc::new_mbc(&cartridge.header)?;
+
+        self.external_ram = vec![0; Self::external_ram_size(&cartridge.header)];
+        self.rom = cartridge.rom;
+
+        if cartridge.header.cgb() {
             self.load_cgb_boot_rom();
             self.cgb_mode = true;
             self.display.cgb_mode = true;
@@ -221,13 +304,50 @@ impl Memory {
             self.load_dmg_boot_rom();
         }
 
+        self.header = Some(cartridge.header);
+
         Ok(())
     }
 
+    fn external_ram_size(header: &Header) -> usize {
+        if header.cartridge_type.mbc == MbcKind::Mbc2 {
+            MBC2_RAM_SIZE
+        } else {
+            header.ram_size
+        }
+    }
+
     pub fn load_external_ram(&mut self, file: &str) -> Result<(), std::io::Error> {
         let mut f = File::open(file)?;
+        let mut data = Vec::new();
+
+        f.read_to_end(&mut data)?;
+
+        let ram_size = self.external_ram.len();
+
+        if data.len() < ram_size {
+            println!(
+                "Warning: the save file is {} bytes long but the cartridge has {} bytes of RAM",
+                data.len(),
+                ram_size
+            );
+        }
+
+        let len = data.len().min(ram_size);
 
-        f.read(&mut self.external_ram)?;
+        self.external_ram[..len].copy_from_slice(&data[..len]);
+
+        // MBC3 clock state, appended after the RAM like BGB and VBA-M do
+        if data.len() > ram_size {
+            if self.mbc.rtc_footer().is_some() {
+                self.mbc.load_rtc_footer(&data[ram_size..]);
+            } else {
+                println!(
+                    "Warning: ignoring {} extra bytes at the end of the save file",
+                    data.len() - ram_size
+                );
+            }
+        }
 
         println!("Save file loaded from \"{}\"!", file);
 
@@ -239,84 +359,126 @@ impl Memory {
 
         f.write_all(&self.external_ram)?;
 
+        if let Some(footer) = self.mbc.rtc_footer() {
+            f.write_all(&footer)?;
+        }
+
         println!("Save written to \"{}\"!", file);
 
         Ok(())
     }
 
-    pub fn r(&self, addr: u16) -> Result<u8, MemError> {
-        if (addr < 0x100 || (addr >= 0x200 && addr < 0x900)) && self.boot_rom_on {
-            Ok(self.boot_rom[addr as usize])
-        } else if addr < 0x4000 {
-            Ok(self.rom[addr as usize])
-        } else if addr < 0x8000 {
-            Ok(self.rom[self.rom_bank as usize * 0x4000 + addr as usize - 0x4000 as usize])
-        } else if addr >= 0xa000 && addr < 0xc000 {
-            if self.ram_bank_enabled {
-                Ok(self.external_ram[self.ram_bank as usize * 0x2000 + addr as usize - 0xa000])
-            } else {
-                Ok(0xff)
+    pub fn tick_cartridge(&mut self, c: u64) {
+        self.mbc.tick(c);
+    }
+
+    pub fn rom_bank(&self) -> usize {
+        self.mbc.rom_bank()
+    }
+
+    pub fn ram_enabled(&self) -> bool {
+        self.mbc.ram_enabled()
+    }
+
+    /// Records the watchpoints triggered by an access, for reads `old` and
+    /// `new` are both the value read.
+    pub(crate) fn check_watchpoints(&self, addr: u16, write: bool, old: u8, new: u8) {
+        for (index, watchpoint) in self.watchpoints.iter().enumerate() {
+            if watchpoint.contains(addr) && watchpoint.triggers(write, old, new) {
+                self.watchpoint_hits.borrow_mut().push(WatchpointHit {
+                    index,
+                    kind: watchpoint.kind,
+                    addr,
+                    old,
+                    new,
+                });
             }
-        } else if addr >= 0xc000 && addr < 0xd000 {
-            Ok(self.wram_00[addr as usize - 0xc000])
-        } else if addr >= 0xd000 && addr < 0xe000 {
-            Ok(self.wram_01[addr as usize - 0xd000])
-        } else if (addr >= 0x8000 && addr < 0xa000) || (addr >= 0xfe00 && addr < 0xfea0) {
+        }
+    }
+
+    pub(crate) fn is_watched(&self, addr: u16) -> bool {
+        self.watchpoints.iter().any(|w| w.contains(addr))
+    }
+
+    pub fn take_watchpoint_hits(&self) -> Vec<WatchpointHit> {
+        self.watchpoint_hits.take()
+    }
+
+    /// Records an error to return once the current instruction is done, only
+    /// the first one is kept.
+    pub(crate) fn raise(&self, e: EmuError) {
+        self.fault.borrow_mut().get_or_insert(e);
+    }
+
+    pub fn take_fault(&self) -> Option<EmuError> {
+        self.fault.take()
+    }
+
+    pub fn r(&self, addr: u16) -> u8 {
+        let value = self.peek(addr);
+
+        if self.is_watched(addr) {
+            self.check_watchpoints(addr, false, value, value);
+        }
+
+        value
+    }
+
+    /// Same as `r` without triggering the watchpoints, for the debugging
+    /// tools
+    pub fn peek(&self, addr: u16) -> u8 {
+        if (addr < 0x100 || (0x200..0x900).contains(&addr)) && self.boot_rom_on {
+            self.boot_rom[addr as usize]
+        } else if addr < 0x8000 {
+            self.mbc.r_rom(&self.rom, addr)
+        } else if (0xa000..0xc000).contains(&addr) {
+            self.mbc.r_ram(&self.external_ram, addr)
+        } else if (0xc000..0xd000).contains(&addr) {
+            self.wram_00[addr as usize - 0xc000]
+        } else if (0xd000..0xe000).contains(&addr) {
+            self.wram_01[addr as usize - 0xd000]
+        } else if (0xe000..0xfe00).contains(&addr) {
+            // Echo RAM, mirror of 0xc000-0xddff
+            self.peek(addr - 0x2000)
+        } else if (0x8000..0xa000).contains(&addr) || (0xfe00..0xfea0).contains(&addr) {
             self.display.r(addr & !0x8000)
-        } else if addr >= 0xff00 && addr < 0xff80 {
-            Ok(self.r_io((addr & 0xff) as u8))
-        } else if addr >= 0xff80 && addr < 0xffff {
-            Ok(self.hram[addr as usize - 0xff80])
+        } else if (0xff00..0xff80).contains(&addr) {
+            self.r_io((addr & 0xff) as u8)
+        } else if (0xff80..0xffff).contains(&addr) {
+            self.hram[addr as usize - 0xff80]
         } else if addr == 0xffff {
-            Ok(self.interrupts_register)
+            self.interrupts_register
         } else {
-            println!(
-                "Trying to read at address 0x{:04x} which is unimplemented",
-                addr
-            );
-            Ok(0) //Err(MemError::Unimplemented)
+            // Unusable area (0xfea0-0xfeff), only visible to the watchpoints
+            0
         }
     }
 
-    pub fn w(&mut self, addr: u16, value: u8) -> Result<(), MemError> {
-        if addr < 0x2000 {
-            self.ram_bank_enabled = value == 0x0a;
-            Ok(())
-        } else if addr >= 0x2000 && addr < 0x4000 {
-            if value == 0 {
-                self.rom_bank = 1
-            } else {
-                self.rom_bank = value & 0b1111111;
-            }
-            Ok(())
-        } else if addr >= 0x4000 && addr < 0x6000 {
-            self.ram_bank = value & 0b11;
-            Ok(())
-        } else if addr >= 0xa000 && addr < 0xc000 {
-            self.external_ram[self.ram_bank as usize * 0x2000 + addr as usize - 0xa000] = value;
-            Ok(())
-        } else if addr >= 0xc000 && addr < 0xd000 {
+    pub fn w(&mut self, addr: u16, value: u8) {
+        // The IO registers are checked by `w_io`
+        if self.is_watched(addr) && !(0xff00..0xff80).contains(&addr) {
+            let old = self.peek(addr);
+            self.check_watchpoints(addr, true, old, value);
+        }
+
+        if addr < 0x8000 {
+            self.mbc.w_rom(addr, value);
+        } else if (0xa000..0xc000).contains(&addr) {
+            self.mbc.w_ram(&mut self.external_ram, addr, value);
+        } else if (0xc000..0xd000).contains(&addr) {
             self.wram_00[addr as usize - 0xc000] = value;
-            Ok(())
-        } else if addr >= 0xd000 && addr < 0xe000 {
+        } else if (0xd000..0xe000).contains(&addr) {
             self.wram_01[addr as usize - 0xd000] = value;
-            Ok(())
-        } else if (addr >= 0x8000 && addr < 0xa000) || (addr >= 0xfe00 && addr < 0xfea0) {
-            self.display.w(addr & !0x8000, value)
-        } else if addr >= 0xff00 && addr < 0xff80 {
-            Ok(self.w_io((addr & 0xff) as u8, value)?)
-        } else if addr >= 0xff80 && addr < 0xffff {
+        } else if (0xe000..0xfe00).contains(&addr) {
+            self.w(addr - 0x2000, value);
+        } else if (0x8000..0xa000).contains(&addr) || (0xfe00..0xfea0).contains(&addr) {
+            self.display.w(addr & !0x8000, value);
+        } else if (0xff00..0xff80).contains(&addr) {
+            self.w_io((addr & 0xff) as u8, value);
+        } else if (0xff80..0xffff).contains(&addr) {
             self.hram[addr as usize - 0xff80] = value;
-            Ok(())
         } else if addr == 0xffff {
             self.interrupts_register = value;
-            Ok(())
-        } else {
-            println!(
-                "Trying to write at address 0x{:04x} which is unimplemented (value: {:02x})",
-                addr, value
-            );
-            Ok(()) //Err(MemError::Unimplemented)
         }
     }
 }
@@ -326,8 +488,18 @@ pub struct GBState {
     pub mem: Memory,
     pub is_debug: bool,
 
-    pub div_cycles: u64,
-    pub tima_cycles: u64,
+    // Not saved in the states, drained by the frontend
+    pub events: Vec<Event>,
+
+    // CPU cycles the rest of the machine was advanced by since the start of
+    // the current step, see `tick`
+    pub(crate) step_cycles: u64,
+}
+
+impl Default for GBState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GBState {
@@ -338,40 +510,181 @@ impl GBState {
             cpu: CPU::new(),
             mem,
             is_debug: false,
+            events: Vec::new(),
 
-            div_cycles: 0,
-            tima_cycles: 0,
+            step_cycles: 0,
         }
     }
 
-    pub fn r_reg(&self, r_i: u8) -> Result<u8, MemError> {
-        if r_i < 6 {
-            Ok(self.cpu.r[r_i as usize])
-        } else if r_i == 7 {
-            Ok(self.cpu.r[6])
-        } else if r_i == 6 {
-            self.mem.r(self.cpu.r16(reg::HL))
-        } else {
-            panic!("r_i must be a 3 bits register input number")
+    /// Blank machine laid out like this one, see `Emulator::load_state`
+    pub(crate) fn scratch(&self) -> Self {
+        Self {
+            cpu: CPU::new(),
+            mem: self.mem.scratch(),
+            is_debug: false,
+            events: Vec::new(),
+
+            step_cycles: 0,
         }
     }
 
-    pub fn w_reg(&mut self, r_i: u8, value: u8) -> Result<(), MemError> {
-        if r_i < 6 {
-            self.cpu.r[r_i as usize] = value;
-        } else if r_i == 7 {
-            self.cpu.r[6] = value;
-        } else if r_i == 6 {
-            self.mem.w(self.cpu.r16(reg::HL), value)?;
-        } else {
-            panic!("r_i must be a 3 bits register input number")
+    /// Memory read by the CPU, taking one M-cycle
+    pub fn read(&mut self, addr: u16) -> u8 {
+        self.tick(4);
+        self.mem.r(addr)
+    }
+
+    /// Memory write by the CPU, taking one M-cycle
+    pub fn write(&mut self, addr: u16, value: u8) {
+        self.tick(4);
+        self.mem.w(addr, value)
+    }
+
+    pub fn r_reg(&mut self, r_i: u8) -> u8 {
+        match r_i {
+            0..=5 => self.cpu.r[r_i as usize],
+            6 => self.read(self.cpu.r16(reg::HL)),
+            _ => self.cpu.r[6],
+        }
+    }
+
+    pub fn w_reg(&mut self, r_i: u8, value: u8) {
+        match r_i {
+            0..=5 => self.cpu.r[r_i as usize] = value,
+            6 => self.write(self.cpu.r16(reg::HL), value),
+            _ => self.cpu.r[6] = value,
         }
-        Ok(())
     }
 
-    pub fn debug(&self, s: &str) -> () {
+    pub fn debug(&self, s: &str) {
         if self.is_debug {
             println!("{}", s);
         }
     }
 }
+
+impl Savable for CPU {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.r);
+        w.u16(self.pc);
+        w.u16(self.sp);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        r.bytes(&mut self.r)?;
+        self.pc = r.u16()?;
+        self.sp = r.u16()?;
+
+        Ok(())
+    }
+}
+
+// The ROM, boot ROM and header come from the cartridge and aren't saved
+impl Savable for Memory {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.cgb_mode);
+        w.u8(self.bgcram_pointer);
+        w.bool(self.bgcram_pointer_autoincrement);
+        w.u8(self.obcram_pointer);
+        w.bool(self.obcram_pointer_autoincrement);
+        w.bool(self.boot_rom_on);
+        self.mbc.save(w);
+        w.bytes(&self.wram_00);
+        w.bytes(&self.wram_01);
+        w.sized_bytes(&self.external_ram);
+        self.display.save(w);
+        w.bytes(&self.io);
+        w.bytes(&self.hram);
+        self.audio.save(w);
+        w.bool(self.ime);
+        self.timer.save(w);
+        self.serial.save(w);
+        w.u8(self.joypad_reg);
+        w.bool(self.joypad_is_action);
+        w.u8(self.interrupts_register);
+        w.bool(self.halt);
+        w.bool(self.stopped);
+        w.bool(self.locked);
+        w.bool(self.double_speed);
+        w.bool(self.speed_switch_armed);
+        w.bool(self.ime_scheduled);
+        w.bool(self.halt_bug);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.cgb_mode = r.bool()?;
+        self.bgcram_pointer = r.u8()?;
+        self.bgcram_pointer_autoincrement = r.bool()?;
+        self.obcram_pointer = r.u8()?;
+        self.obcram_pointer_autoincrement = r.bool()?;
+        self.boot_rom_on = r.bool()?;
+        self.mbc.load(r)?;
+        r.bytes(&mut self.wram_00)?;
+        r.bytes(&mut self.wram_01)?;
+        r.sized_bytes(&mut self.external_ram, "external RAM size")?;
+        self.display.load(r)?;
+        r.bytes(&mut self.io)?;
+        r.bytes(&mut self.hram)?;
+        self.audio.load(r)?;
+        self.ime = r.bool()?;
+        self.timer.load(r)?;
+        self.serial.load(r)?;
+        self.joypad_reg = r.u8()?;
+        self.joypad_is_action = r.bool()?;
+        self.interrupts_register = r.u8()?;
+        self.halt = r.bool()?;
+        self.stopped = r.bool()?;
+        self.locked = r.bool()?;
+        self.double_speed = r.bool()?;
+        self.speed_switch_armed = r.bool()?;
+        self.ime_scheduled = r.bool()?;
+        self.halt_bug = r.bool()?;
+
+        Ok(())
+    }
+}
+
+impl Savable for GBState {
+    fn save(&self, w: &mut StateWriter) {
+        self.cpu.save(w);
+        self.mem.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.cpu.load(r)?;
+        self.mem.load(r)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::WatchKind;
+
+    #[test]
+    fn unusable_memory() {
+        let mut mem = Memory::new();
+        mem.watchpoints = vec![
+            Watchpoint::parse(WatchKind::Read, "fea0-feff", None).unwrap(),
+            Watchpoint::parse(WatchKind::Write, "fea0-feff", None).unwrap(),
+        ];
+
+        // Ignored like on hardware, only the watchpoints see them
+        mem.w(0xfeb0, 0x42);
+        assert_eq!(mem.r(0xfeb0), 0);
+        assert!(mem.take_fault().is_none());
+
+        let hits = mem.take_watchpoint_hits();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(
+            (hits[0].kind, hits[0].addr, hits[0].new),
+            (WatchKind::Write, 0xfeb0, 0x42)
+        );
+        assert_eq!(
+            (hits[1].kind, hits[1].addr, hits[1].new),
+            (WatchKind::Read, 0xfeb0, 0)
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use crate::emulator::Emulator;
+use crate::error::EmuError;
+use std::fmt;
+
+// Registers B, C, D, E, H and L when a Mooneye test stops on LD B,B
+const MOONEYE_PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAILED: [u8; 6] = [0x42; 6];
+
+// LD B,B, used by the Mooneye tests as a software breakpoint
+const LD_B_B: u8 = 0x40;
+
+/// How a test ROM run ended
+#[derive(Debug)]
+pub enum TestOutcome {
+    Passed,
+    Failed,
+    // Neither a result on the serial port nor the Mooneye signature in time
+    Timeout,
+    // Illegal opcode, I/O error...
+    Error(EmuError),
+}
+
+impl fmt::Display for TestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TestOutcome::Passed => write!(f, "passed"),
+            TestOutcome::Failed => write!(f, "FAILED"),
+            TestOutcome::Timeout => write!(f, "TIMEOUT"),
+            TestOutcome::Error(_) => write!(f, "ERROR"),
+        }
+    }
+}
+
+pub struct TestReport {
+    pub outcome: TestOutcome,
+    // CPU cycles run before the result was known
+    pub cycles: u64,
+    // Text sent through the link port, non-printable bytes replaced
+    pub serial: String,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        matches!(self.outcome, TestOutcome::Passed)
+    }
+}
+
+/// Runs a test ROM headlessly for at most `max_cycles` CPU cycles and tells
+/// whether it passed.
+///
+/// Blargg's tests print "Passed" or "Failed" through the serial port, while
+/// the Mooneye ones execute LD B,B once done, with the Fibonacci numbers in
+/// B, C, D, E, H and L on success and 0x42 in all of them on failure.
+pub fn run_test_rom(file: &str, max_cycles: u64) -> Result<TestReport, EmuError> {
+    let mut emulator = Emulator::new();
+    emulator.load_rom(file)?;
+
+    let mut serial = String::new();
+    let mut cycles = 0;
+
+    let outcome = loop {
+        if cycles >= max_cycles {
+            break TestOutcome::Timeout;
+        }
+
+        let state = &emulator.state;
+        if !state.mem.halt && !state.mem.locked && state.mem.peek(state.cpu.pc) == LD_B_B {
+            let registers = &state.cpu.r[..6];
+
+            if registers == MOONEYE_PASSED {
+                break TestOutcome::Passed;
+            } else if registers == MOONEYE_FAILED {
+                break TestOutcome::Failed;
+            }
+        }
+
+        match emulator.step_instruction() {
+            Ok(c) => cycles += c,
+            Err(e) => break TestOutcome::Error(e),
+        }
+
+        let output = emulator.serial_output();
+        if output.is_empty() {
+            continue;
+        }
+
+        serial.extend(output.into_iter().map(|byte| {
+            if byte.is_ascii_graphic() || byte.is_ascii_whitespace() {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+
+        if serial.contains("Passed") {
+            break TestOutcome::Passed;
+        } else if serial.contains("Failed") {
+            break TestOutcome::Failed;
+        }
+    };
+
+    Ok(TestReport {
+        outcome,
+        cycles,
+        serial,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Header;
+
+    // 10 seconds, the boot ROM takes about 6 to scroll the logo
+    const MAX_CYCLES: u64 = 10 * crate::consts::CPU_CLOCK_SPEED;
+
+    /// Writes a ROM running `program` at 0x150 after the boot ROM, with
+    /// `data` at 0x200, and runs it
+    fn run(name: &str, program: &[u8], data: &[u8], max_cycles: u64) -> TestReport {
+        let mut rom = vec![0; 0x8000];
+
+        // NOP, JP 0x150 and the logo checked by the boot ROM
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        rom[0x104..0x134].copy_from_slice(&include_bytes!("../assets/dmg_boot.bin")[0xa8..0xd8]);
+        rom[0x14d] = Header::compute_header_checksum(&rom);
+        rom[0x150..0x150 + program.len()].copy_from_slice(program);
+        rom[0x200..0x200 + data.len()].copy_from_slice(data);
+
+        let file = std::env::temp_dir().join(format!(
+            "emulator-testrom-{}-{}.gb",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&file, rom).unwrap();
+        let report = run_test_rom(&file.to_string_lossy(), max_cycles);
+        std::fs::remove_file(&file).ok();
+
+        report.unwrap()
+    }
+
+    /// Loads B, C, D, E, H and L and stops on LD B,B
+    fn mooneye(registers: [u8; 6]) -> Vec<u8> {
+        let mut program = Vec::new();
+        for (opcode, value) in [0x06, 0x0e, 0x16, 0x1e, 0x26, 0x2e]
+            .into_iter()
+            .zip(registers)
+        {
+            program.extend([opcode, value]);
+        }
+        // LD B,B then JR to itself
+        program.extend([LD_B_B, 0x18, 0xfe]);
+
+        program
+    }
+
+    // Sends the string at 0x200 through the serial port
+    const PRINT: [u8; 22] = [
+        0x21, 0x00, 0x02, // LD HL, 0x200
+        0x2a, // LD A, (HL+)
+        0xb7, // OR A
+        0x28, 0x0d, // JR Z, end
+        0xe0, 0x01, // LDH (SB), A
+        0x3e, 0x81, // LD A, 0x81
+        0xe0, 0x02, // LDH (SC), A
+        0xf0, 0x02, // LDH A, (SC)
+        0x17, // RLA
+        0x38, 0xfb, // JR C, -5 (until the transfer is done)
+        0x18, 0xef, // JR -17 (next character)
+        0x18, 0xfe, // end: JR to itself
+    ];
+
+    #[test]
+    fn mooneye_passed() {
+        let report = run("mooneye-passed", &mooneye(MOONEYE_PASSED), &[], MAX_CYCLES);
+        assert!(report.passed(), "{}", report.outcome);
+    }
+
+    #[test]
+    fn mooneye_failed() {
+        let report = run("mooneye-failed", &mooneye(MOONEYE_FAILED), &[], MAX_CYCLES);
+        assert!(matches!(report.outcome, TestOutcome::Failed));
+    }
+
+    #[test]
+    fn serial_passed() {
+        let report = run("serial-passed", &PRINT, b"Test\nPassed\n\0", MAX_CYCLES);
+        assert!(report.passed(), "{}", report.outcome);
+        assert_eq!(report.serial, "Test\nPassed");
+    }
+
+    #[test]
+    fn serial_failed() {
+        let report = run("serial-failed", &PRINT, b"Failed #2\0", MAX_CYCLES);
+        assert!(matches!(report.outcome, TestOutcome::Failed));
+        assert_eq!(report.serial, "Failed");
+    }
+
+    #[test]
+    fn timeout() {
+        // Still in the boot ROM
+        let report = run("timeout", &mooneye(MOONEYE_PASSED), &[], 1000);
+        assert!(matches!(report.outcome, TestOutcome::Timeout));
+        assert!(report.cycles >= 1000);
+    }
+}
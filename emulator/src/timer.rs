@@ -0,0 +1,305 @@
+use crate::savestate::{Savable, SaveStateError, StateReader, StateWriter};
+
+// Bit of the divider clocking TIMA for each TAC speed (4096, 262144, 65536
+// and 16384 Hz)
+const TIMA_DIVIDER_BITS: [u16; 4] = [9, 3, 5, 7];
+
+/// DIV and TIMA registers. Both are driven by a 16 bits counter incremented
+/// every cycle, DIV being its high byte. TIMA is incremented on the falling
+/// edges of one of its bits (ANDed with the TAC enable bit), so resetting DIV
+/// or changing TAC can increment it too, like on hardware.
+#[derive(Debug, Clone)]
+pub struct Timer {
+    pub divider: u16,
+
+    pub tima: u8,
+
+    pub tma: u8,
+
+    pub enabled: bool,
+
+    pub speed: u8,
+
+    // TIMA overflowed during the last M-cycle, it stays at 0 until it is
+    // reloaded from TMA in the next one, unless it is written in between
+    overflow: bool,
+
+    // TIMA was reloaded during the last M-cycle, writing TIMA is ignored and
+    // writing TMA is also copied to TIMA
+    reloaded: bool,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self {
+            divider: 0,
+            tima: 0,
+            tma: 0,
+            enabled: false,
+            speed: 0,
+            overflow: false,
+            reloaded: false,
+        }
+    }
+
+    // Input of the falling edge detector incrementing TIMA
+    fn signal(&self) -> bool {
+        self.enabled && self.divider & (1 << TIMA_DIVIDER_BITS[self.speed as usize]) != 0
+    }
+
+    fn increment_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+
+        self.tima = tima;
+        self.overflow = overflow;
+    }
+
+    // Applies a change of the divider or TAC
+    fn update_signal(&mut self, f: impl FnOnce(&mut Self)) {
+        let signal = self.signal();
+
+        f(self);
+
+        if signal && !self.signal() {
+            self.increment_tima();
+        }
+    }
+
+    /// Advances the timer by `c` CPU cycles, one M-cycle at a time. Returns
+    /// true when the timer interrupt is requested.
+    pub fn tick(&mut self, c: u64) -> bool {
+        let mut interrupt = false;
+
+        for _ in 0..c / 4 {
+            self.reloaded = false;
+
+            if self.overflow {
+                self.overflow = false;
+                self.reloaded = true;
+                self.tima = self.tma;
+                interrupt = true;
+            }
+
+            self.update_signal(|timer| timer.divider = timer.divider.wrapping_add(4));
+        }
+
+        interrupt
+    }
+
+    /// Writing any value to DIV resets the whole divider
+    pub fn reset_divider(&mut self) {
+        self.update_signal(|timer| timer.divider = 0);
+    }
+
+    pub fn r(&self, addr: u8) -> u8 {
+        match addr {
+            0x04 => (self.divider >> 8) as u8,
+            0x05 => self.tima,
+            0x06 => self.tma,
+            _ => 0b11111000 | (self.enabled as u8) << 2 | self.speed,
+        }
+    }
+
+    pub fn w(&mut self, addr: u8, value: u8) {
+        match addr {
+            0x04 => self.reset_divider(),
+            0x05 => {
+                // Cancels a pending reload, the interrupt included
+                if !self.reloaded {
+                    self.tima = value;
+                    self.overflow = false;
+                }
+            }
+            0x06 => {
+                self.tma = value;
+
+                if self.reloaded {
+                    self.tima = value;
+                }
+            }
+            _ => self.update_signal(|timer| {
+                timer.enabled = value & 0b100 != 0;
+                timer.speed = value & 0b11;
+            }),
+        }
+    }
+}
+
+impl Savable for Timer {
+    fn save(&self, w: &mut StateWriter) {
+        w.u16(self.divider);
+        w.u8(self.tima);
+        w.u8(self.tma);
+        w.bool(self.enabled);
+        w.u8(self.speed);
+        w.bool(self.overflow);
+        w.bool(self.reloaded);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.divider = r.u16()?;
+        self.tima = r.u8()?;
+        self.tma = r.u8()?;
+        self.enabled = r.bool()?;
+        self.speed = r.u8()? & 0b11;
+        self.overflow = r.bool()?;
+        self.reloaded = r.bool()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // TAC value enabling the timer at 262144 Hz, TIMA follows bit 3
+    const TAC_16_CYCLES: u8 = 0b101;
+
+    fn with_tac(tac: u8) -> Timer {
+        let mut timer = Timer::new();
+        timer.w(0x07, tac);
+
+        timer
+    }
+
+    #[test]
+    fn frequencies() {
+        for (speed, period) in [(0, 1024), (1, 16), (2, 64), (3, 256)] {
+            let mut timer = with_tac(0b100 | speed);
+
+            // Incremented when the bit falls, after a whole period
+            timer.tick(period - 4);
+            assert_eq!(timer.tima, 0, "speed {}", speed);
+            timer.tick(4);
+            assert_eq!(timer.tima, 1, "speed {}", speed);
+
+            timer.tick(period * 9);
+            assert_eq!(timer.tima, 10, "speed {}", speed);
+        }
+
+        // Nothing when disabled
+        let mut timer = with_tac(0b001);
+        timer.tick(1024);
+        assert_eq!(timer.tima, 0);
+    }
+
+    #[test]
+    fn div_write_glitch() {
+        // Bit 3 high, resetting the divider is a falling edge
+        let mut timer = with_tac(TAC_16_CYCLES);
+        timer.tick(8);
+        timer.w(0x04, 0);
+        assert_eq!(timer.tima, 1);
+        assert_eq!(timer.divider, 0);
+
+        // Bit 3 low, nothing happens
+        timer.tick(4);
+        timer.w(0x04, 0);
+        assert_eq!(timer.tima, 1);
+
+        // The next increment is a whole period after the reset
+        timer.tick(12);
+        assert_eq!(timer.tima, 1);
+        timer.tick(4);
+        assert_eq!(timer.tima, 2);
+    }
+
+    #[test]
+    fn tac_write_glitch() {
+        // Disabling the timer while bit 3 is high
+        let mut timer = with_tac(TAC_16_CYCLES);
+        timer.tick(8);
+        timer.w(0x07, 0b001);
+        assert_eq!(timer.tima, 1);
+
+        // Enabling it is a rising edge
+        timer.w(0x07, TAC_16_CYCLES);
+        assert_eq!(timer.tima, 1);
+
+        // Switching from bit 3 (high) to bit 9 (low)
+        timer.w(0x07, 0b100);
+        assert_eq!(timer.tima, 2);
+
+        // Switching between two high bits
+        let mut timer = with_tac(TAC_16_CYCLES);
+        timer.divider = 0x0208;
+        timer.w(0x07, 0b100);
+        assert_eq!(timer.tima, 0);
+
+        // Disabling it while the bit is low
+        let mut timer = with_tac(TAC_16_CYCLES);
+        timer.tick(4);
+        timer.w(0x07, 0b001);
+        assert_eq!(timer.tima, 0);
+    }
+
+    /// Timer which overflows at the end of its next M-cycle
+    fn overflowing() -> Timer {
+        let mut timer = with_tac(TAC_16_CYCLES);
+        timer.tima = 0xff;
+        timer.tma = 0x42;
+        timer.tick(12);
+        assert_eq!(timer.tima, 0xff);
+
+        timer
+    }
+
+    #[test]
+    fn overflow() {
+        let mut timer = overflowing();
+
+        // 0x00 for one M-cycle, then reloaded with the interrupt
+        assert!(!timer.tick(4));
+        assert_eq!(timer.r(0x05), 0x00);
+        assert!(timer.tick(4));
+        assert_eq!(timer.r(0x05), 0x42);
+
+        // Counting from TMA
+        assert!(!timer.tick(8));
+        assert_eq!(timer.tima, 0x42);
+        assert!(!timer.tick(4));
+        assert_eq!(timer.tima, 0x43);
+
+        // Both in a single tick
+        let mut timer = overflowing();
+        assert!(timer.tick(8));
+        assert_eq!(timer.tima, 0x42);
+    }
+
+    #[test]
+    fn tima_write_during_overflow() {
+        let mut timer = overflowing();
+        timer.tick(4);
+
+        // Cancels the reload and the interrupt
+        timer.w(0x05, 0x10);
+        assert!(!timer.tick(4));
+        assert_eq!(timer.tima, 0x10);
+    }
+
+    #[test]
+    fn writes_during_reload() {
+        let mut timer = overflowing();
+        timer.tick(8);
+
+        // TIMA writes are ignored, TMA ones are copied to TIMA
+        timer.w(0x05, 0x10);
+        assert_eq!(timer.tima, 0x42);
+        timer.w(0x06, 0x77);
+        assert_eq!((timer.tima, timer.tma), (0x77, 0x77));
+
+        // Back to normal on the next M-cycle
+        timer.tick(4);
+        timer.w(0x06, 0x88);
+        assert_eq!((timer.tima, timer.tma), (0x77, 0x88));
+        timer.w(0x05, 0x10);
+        assert_eq!(timer.tima, 0x10);
+    }
+}
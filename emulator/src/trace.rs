@@ -0,0 +1,178 @@
+use crate::state::{reg, GBState};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// Writes one line per instruction in the Gameboy Doctor format, to compare
+/// the execution with the logs of other emulators:
+///
+/// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+///
+/// The boot ROM isn't logged, the logs of reference start at 0x0100 once it
+/// is unmapped. The cycles elapsed since the start and LY can be appended to
+/// each line, as ` CY:<cycles> LY:<ly>`.
+pub struct Tracer {
+    out: BufWriter<Box<dyn Write + Send>>,
+
+    pub cycles: bool,
+    pub ly: bool,
+
+    total_cycles: u64,
+    // The file is flushed once per frame, in case the emulator is killed
+    last_frame: u64,
+}
+
+impl Tracer {
+    pub fn new(file: &str) -> io::Result<Self> {
+        Ok(Self::with_writer(Box::new(File::create(file)?)))
+    }
+
+    /// Writes the lines to `out` instead of a file
+    pub fn with_writer(out: Box<dyn Write + Send>) -> Self {
+        Self {
+            out: BufWriter::new(out),
+            cycles: false,
+            ly: false,
+            total_cycles: 0,
+            last_frame: 0,
+        }
+    }
+
+    /// Called before each instruction
+    pub fn before_instruction(&mut self, state: &GBState) -> io::Result<()> {
+        if state.mem.boot_rom_on {
+            return Ok(());
+        }
+
+        let cpu = &state.cpu;
+        let pcmem: Vec<String> = (0..4)
+            .map(|i| format!("{:02X}", state.mem.peek(cpu.pc.wrapping_add(i))))
+            .collect();
+
+        write!(
+            self.out,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}",
+            cpu.r[reg::A as usize],
+            cpu.r[reg::F as usize],
+            cpu.r[reg::B as usize],
+            cpu.r[reg::C as usize],
+            cpu.r[reg::D as usize],
+            cpu.r[reg::E as usize],
+            cpu.r[reg::H as usize],
+            cpu.r[reg::L as usize],
+            cpu.sp,
+            cpu.pc,
+            pcmem.join(",")
+        )?;
+
+        if self.cycles {
+            write!(self.out, " CY:{}", self.total_cycles)?;
+        }
+        if self.ly {
+            write!(self.out, " LY:{:02X}", state.mem.display.ly)?;
+        }
+        writeln!(self.out)?;
+
+        if state.mem.display.frames != self.last_frame {
+            self.last_frame = state.mem.display.frames;
+            self.out.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Counts the cycles elapsed, including the ones spent halted
+    pub fn tick(&mut self, c: u64) {
+        self.total_cycles += c;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // Shared with the test to read back what the tracer wrote
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Post boot ROM state of the DMG, with the entry point of a cartridge in
+    /// WRAM
+    fn state() -> GBState {
+        let mut state = GBState::new();
+        state.mem.boot_rom_on = false;
+
+        let cpu = &mut state.cpu;
+        cpu.r[reg::A as usize] = 0x01;
+        cpu.r[reg::F as usize] = 0xb0;
+        cpu.r[reg::B as usize] = 0x00;
+        cpu.r[reg::C as usize] = 0x13;
+        cpu.r[reg::D as usize] = 0x00;
+        cpu.r[reg::E as usize] = 0xd8;
+        cpu.r[reg::H as usize] = 0x01;
+        cpu.r[reg::L as usize] = 0x4d;
+        cpu.sp = 0xfffe;
+        cpu.pc = 0xc000;
+
+        for (i, byte) in [0x00, 0xc3, 0x13, 0x02].into_iter().enumerate() {
+            state.mem.w(0xc000 + i as u16, byte);
+        }
+        state.mem.display.ly = 0x90;
+
+        state
+    }
+
+    fn trace(state: &GBState, cycles: bool, ly: bool) -> String {
+        let output = Output::default();
+        let mut tracer = Tracer::with_writer(Box::new(output.clone()));
+        tracer.cycles = cycles;
+        tracer.ly = ly;
+
+        tracer.tick(1234);
+        tracer.before_instruction(state).unwrap();
+        tracer.tick(4);
+        tracer.before_instruction(state).unwrap();
+        drop(tracer);
+
+        let output = output.0.lock().unwrap();
+        String::from_utf8(output.clone()).unwrap()
+    }
+
+    const LINE: &str = "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:C000 PCMEM:00,C3,13,02";
+
+    #[test]
+    fn lines() {
+        let state = state();
+
+        assert_eq!(trace(&state, false, false), format!("{LINE}\n{LINE}\n"));
+        assert_eq!(
+            trace(&state, true, false),
+            format!("{LINE} CY:1234\n{LINE} CY:1238\n")
+        );
+        assert_eq!(
+            trace(&state, false, true),
+            format!("{LINE} LY:90\n{LINE} LY:90\n")
+        );
+        assert_eq!(
+            trace(&state, true, true),
+            format!("{LINE} CY:1234 LY:90\n{LINE} CY:1238 LY:90\n")
+        );
+    }
+
+    #[test]
+    fn boot_rom() {
+        let mut state = state();
+        state.mem.boot_rom_on = true;
+
+        assert_eq!(trace(&state, true, true), "");
+    }
+}
@@ -0,0 +1,167 @@
+//! Screenshot regression tests, for the test ROMs judged visually
+//! (dmg-acid2, cgb-acid2, Mealybug Tearoom...).
+//!
+//! Each line of `screenshots.txt` in `tests/screenshots`, or the directory
+//! given in `SCREENSHOT_TESTS_DIR`, gives a ROM, the number of frames to run
+//! it for and the reference PNG to compare the screen with, relative to that
+//! directory:
+//!
+//! ```text
+//! # ROM            frames  reference
+//! dmg-acid2.gb     60      dmg-acid2-dmg.png
+//! ```
+//!
+//! The test is skipped when `tests/screenshots` doesn't have this file, the
+//! ROMs aren't distributed with the emulator, but fails when
+//! `SCREENSHOT_TESTS_DIR` doesn't. The frames are counted by the display, a
+//! ROM keeping the LCD off for more than twice their duration fails. On a
+//! mismatch the screenshot taken and an image of the pixels that differ are
+//! written next to the test binaries, in `target/tmp/screenshots`.
+
+use emulator::consts::CYCLES_PER_FRAME;
+use emulator::screenshot::Image;
+use emulator::Emulator;
+use std::path::{Path, PathBuf};
+
+struct ScreenshotTest {
+    rom: PathBuf,
+    frames: u64,
+    reference: PathBuf,
+}
+
+fn parse_tests(dir: &Path, manifest: &str) -> Vec<ScreenshotTest> {
+    manifest
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                return None;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [rom, frames, reference] => Some(ScreenshotTest {
+                    rom: dir.join(rom),
+                    frames: frames
+                        .parse()
+                        .unwrap_or_else(|_| panic!("line {}: invalid frames {:?}", i + 1, frames)),
+                    reference: dir.join(reference),
+                }),
+                _ => panic!("line {}: expected <rom> <frames> <reference>", i + 1),
+            }
+        })
+        .collect()
+}
+
+/// Runs the ROM and compares its screen with the reference, returns what
+/// went wrong otherwise
+fn run_test(test: &ScreenshotTest, output_dir: &Path) -> Result<(), String> {
+    let mut emulator = Emulator::new();
+    emulator
+        .load_rom(&test.rom.to_string_lossy())
+        .map_err(|e| e.to_string())?;
+
+    // The frames run with the LCD off don't count
+    let mut cycles = 0;
+    while emulator.state.mem.display.frames < test.frames {
+        if cycles > 2 * test.frames * CYCLES_PER_FRAME {
+            return Err(format!(
+                "only {} frames displayed",
+                emulator.state.mem.display.frames
+            ));
+        }
+
+        cycles += emulator.run_frame().map_err(|e| e.to_string())?;
+    }
+
+    let screenshot =
+        Image::from_framebuffer(emulator.framebuffer(), emulator.state.mem.display.cgb_mode);
+    let name = test.reference.file_stem().unwrap().to_string_lossy();
+    let save = |image: &Image, file: String| {
+        let file = output_dir.join(file);
+        image
+            .save_png(&file.to_string_lossy())
+            .map(|_| file.display().to_string())
+            .map_err(|e| e.to_string())
+    };
+
+    // The screenshot is saved anyway, to check it and make it the reference
+    let reference = match Image::load_png(&test.reference.to_string_lossy()) {
+        Ok(reference) => reference,
+        Err(e) => {
+            return Err(format!(
+                "{}: {}, see {}",
+                test.reference.display(),
+                e,
+                save(&screenshot, format!("{}.png", name))?
+            ))
+        }
+    };
+
+    match screenshot.diff(&reference) {
+        Some((0, _)) => Ok(()),
+        Some((count, diff)) => Err(format!(
+            "{} pixels differ, see {} and {}",
+            count,
+            save(&screenshot, format!("{}.png", name))?,
+            save(&diff, format!("{}.diff.png", name))?
+        )),
+        None => Err(format!(
+            "the reference is {}x{}, see {}",
+            reference.width,
+            reference.height,
+            save(&screenshot, format!("{}.png", name))?
+        )),
+    }
+}
+
+#[test]
+fn screenshots() {
+    let env_dir = std::env::var_os("SCREENSHOT_TESTS_DIR");
+    let dir = env_dir
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/screenshots"));
+
+    let manifest = match std::fs::read_to_string(dir.join("screenshots.txt")) {
+        Ok(manifest) => manifest,
+        // Most likely a typo in the path given
+        Err(e) if env_dir.is_some() => panic!(
+            "Failed to read screenshots.txt in SCREENSHOT_TESTS_DIR ({}): {}",
+            dir.display(),
+            e
+        ),
+        Err(_) => {
+            println!("No screenshots.txt in {}, skipping", dir.display());
+            return;
+        }
+    };
+
+    let output_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("screenshots");
+    std::fs::create_dir_all(&output_dir).unwrap();
+
+    let tests = parse_tests(&dir, &manifest);
+    let mut failed = 0;
+
+    for test in &tests {
+        let rom = test.rom.strip_prefix(&dir).unwrap_or(&test.rom).display();
+
+        match run_test(test, &output_dir) {
+            Ok(()) => println!("passed  {}", rom),
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {} ({})", rom, e);
+            }
+        }
+    }
+
+    println!(
+        "{} passed, {} failed out of {} screenshot tests",
+        tests.len() - failed,
+        failed,
+        tests.len()
+    );
+
+    assert_eq!(failed, 0, "{} screenshot tests failed", failed);
+}
@@ -0,0 +1,123 @@
+//! Runs every test ROM (`.gb` and `.gbc`) found under `tests/roms`, or the
+//! directory given in `TEST_ROMS_DIR`, and prints a summary table. The test
+//! is skipped when `tests/roms` is missing or empty, the ROMs aren't
+//! distributed with the emulator, but fails when `TEST_ROMS_DIR` has none.
+//!
+//! `TEST_ROMS_CYCLES` bounds the CPU cycles run per ROM (one minute of
+//! emulated time by default).
+
+use emulator::consts::CPU_CLOCK_SPEED;
+use emulator::testrom::{run_test_rom, TestOutcome, TestReport};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "gb" || extension == "gbc")
+        {
+            roms.push(path);
+        }
+    }
+}
+
+#[test]
+fn test_roms() {
+    let env_dir = std::env::var_os("TEST_ROMS_DIR");
+    let dir = env_dir
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms"));
+
+    let mut roms = Vec::new();
+    find_roms(&dir, &mut roms);
+    roms.sort();
+
+    if roms.is_empty() {
+        // Most likely a typo in the path given
+        assert!(
+            env_dir.is_none(),
+            "No test ROMs in TEST_ROMS_DIR ({})",
+            dir.display()
+        );
+
+        println!("No test ROMs in {}, skipping", dir.display());
+        return;
+    }
+
+    let max_cycles = std::env::var("TEST_ROMS_CYCLES")
+        .ok()
+        .and_then(|cycles| cycles.parse().ok())
+        .unwrap_or(60 * CPU_CLOCK_SPEED);
+
+    // The ROMs are independent, run them on every core
+    let queue = Mutex::new(roms.iter().enumerate());
+    let results = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for _ in 0..thread::available_parallelism().map_or(1, |n| n.get()) {
+            scope.spawn(|| loop {
+                let Some((i, rom)) = queue.lock().unwrap().next() else {
+                    break;
+                };
+
+                let report = run_test_rom(&rom.to_string_lossy(), max_cycles);
+                results.lock().unwrap().push((i, report));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+
+    let mut failed = 0;
+
+    println!("{:<8} {:>8}  ROM", "Result", "Seconds");
+    for (i, report) in results {
+        let rom = roms[i].strip_prefix(&dir).unwrap_or(&roms[i]).display();
+
+        match report {
+            Ok(TestReport {
+                outcome, cycles, ..
+            }) => {
+                if !matches!(outcome, TestOutcome::Passed) {
+                    failed += 1;
+                }
+
+                print!(
+                    "{:<8} {:>8.1}  {}",
+                    outcome.to_string(),
+                    cycles as f64 / CPU_CLOCK_SPEED as f64,
+                    rom
+                );
+                match outcome {
+                    TestOutcome::Error(e) => println!(" ({})", e),
+                    _ => println!(),
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                println!("{:<8} {:>8}  {} ({})", "ERROR", "-", rom, e);
+            }
+        }
+    }
+
+    println!(
+        "{} passed, {} failed out of {} test ROMs",
+        roms.len() - failed,
+        failed,
+        roms.len()
+    );
+
+    assert_eq!(failed, 0, "{} test ROMs didn't pass", failed);
+}